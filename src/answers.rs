@@ -0,0 +1,64 @@
+//! The `--remember-answers` file keeping link creation answers across runs.
+//!
+//! Each line is `yes<TAB>source` or `no<TAB>source`, sorted by source so the file can be edited by hand. Deleting a
+//! line makes tlu ask about that source again.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Loads remembered answers. A missing file remembers nothing. Empty lines are ignored.
+pub fn load(path: &Path) -> io::Result<HashMap<PathBuf, bool>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut answers = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (source, answer) = match line.split_once('\t') {
+            Some(("yes", source)) => (source, true),
+            Some(("no", source)) => (source, false),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: expected `yes<TAB>source` or `no<TAB>source`", number + 1),
+                ))
+            }
+        };
+        answers.insert(PathBuf::from(source), answer);
+    }
+
+    Ok(answers)
+}
+
+/// Writes `answers` to `path`, replacing it only once the new file is complete.
+pub fn save(path: &Path, answers: &HashMap<PathBuf, bool>) -> io::Result<()> {
+    let mut lines: Vec<(&PathBuf, bool)> = answers
+        .iter()
+        // Newlines would split the entry.
+        .filter(|(source, _)| !source.to_string_lossy().contains(['\n', '\r']))
+        .map(|(source, &answer)| (source, answer))
+        .collect();
+    lines.sort();
+
+    let mut content = String::new();
+    for (source, answer) in lines {
+        content.push_str(if answer { "yes\t" } else { "no\t" });
+        content.push_str(&source.to_string_lossy());
+        content.push('\n');
+    }
+
+    let mut temporary = path.file_name().unwrap_or_default().to_owned();
+    temporary.push(".tmp");
+    let temporary = path.with_file_name(temporary);
+    fs::write(&temporary, content)?;
+    fs::rename(&temporary, path)
+}
@@ -0,0 +1,55 @@
+//! `--atomic-run`: building the links in a staging directory and swapping it with <TARGET> at the end.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// `target` with `suffix` appended to its file name.
+fn sibling(target: &Path, suffix: &str) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_owned();
+    name.push(suffix);
+    target.with_file_name(name)
+}
+
+/// Creates the empty `<target>.tmp` staging directory and returns its path. An existing staging directory is an
+/// error, it's most likely left over from an interrupted run.
+pub fn stage(target: &Path) -> io::Result<PathBuf> {
+    let staging = sibling(&fs::canonicalize(target)?, ".tmp");
+    fs::create_dir(&staging).map_err(|err| {
+        if err.kind() == io::ErrorKind::AlreadyExists {
+            io::Error::new(
+                err.kind(),
+                format!("staging directory `{}` already exists, remove it first", staging.display()),
+            )
+        } else {
+            err
+        }
+    })?;
+
+    Ok(staging)
+}
+
+/// Moves `target` to `<target>.old`, replacing an earlier one, and puts `staging` in its place.
+pub fn swap(staging: &Path, target: &Path) -> io::Result<()> {
+    let target = fs::canonicalize(target)?;
+    let target = target.as_path();
+    let old = sibling(target, ".old");
+    if old.symlink_metadata().is_ok() {
+        fs::remove_dir_all(&old)?;
+    }
+
+    fs::rename(target, &old)?;
+    if let Err(err) = fs::rename(staging, target) {
+        // Put the original back so <TARGET> doesn't go missing.
+        fs::rename(&old, target)?;
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Throws away the staging directory of a failed run.
+pub fn roll_back(staging: &Path) -> io::Result<()> {
+    fs::remove_dir_all(staging)
+}
@@ -1,8 +1,17 @@
-use std::{fs, io, path::{Path, PathBuf}};
+use std::{
+    env,
+    fmt::Write,
+    io,
+    num::{NonZeroU64, NonZeroUsize},
+    iter,
+    path::{self, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
-use clap::{Parser, ValueEnum};
+use clap::{ArgAction, Parser, ValueEnum};
 use clap_complete::Shell;
 
+use crate::{glob, hook::ShellCommand, layout::{FlattenNaming, Layout}, link::LinkStrategy, names, summary::ReportFormat};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum Action {
@@ -11,23 +20,242 @@ pub enum Action {
     Never,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Report the existing destination as an error
+    Error,
+    /// Leave the existing destination alone
+    Skip,
+    /// Replace the existing destination with the new link
+    Overwrite,
+    /// Like overwrite, but only if the source was modified more recently than the existing destination
+    Newer,
+    /// Move the existing destination aside, to `<name>.bak` or into --backup-dir, then link
+    Backup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnOrphan {
+    /// List them
+    Report,
+    /// Delete them, asking first unless --never-prompt or --yes is given
+    Remove,
+    /// Don't look for them
+    Ignore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnPermissionDenied {
+    /// Report the error and carry on
+    Warn,
+    /// Quietly skip the unreadable entry and everything below it
+    #[value(alias = "skip")]
+    SkipTree,
+    /// Report the error and stop the run
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SymlinkPolicy {
+    /// Point at the source relative to the link's directory
+    Relative,
+    /// Point at the absolute path of the source
+    Absolute,
+    /// Relative if the source is inside <TARGET>, absolute otherwise
+    Hybrid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnSymlinkPrivilegeError {
+    /// Create a hard link instead. Doesn't work for --dir-link
+    Hardlink,
+    /// Stop the run
+    Abort,
+    /// Copy the file instead. Doesn't work for --dir-link
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NoninteractiveFallback {
+    /// Refuse to run
+    Abort,
+    /// Answer every prompt with yes, like -f and -y
+    AssumeYes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WithinDirOrder {
+    /// Handle entries in the order the directory is read in, mixing files and directories
+    Read,
+    /// Handle the files of a directory before its subdirectories
+    FilesFirst,
+    /// Handle the subdirectories of a directory before its files
+    DirsFirst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProvenanceFormat {
+    /// `key=value` lines
+    Text,
+    /// A single JSON object
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LinkKind {
+    Hard,
+    Symbolic,
+}
+
+impl LinkKind {
+    pub const fn file_strategy(self) -> LinkStrategy {
+        match self {
+            Self::Hard => LinkStrategy::Hard,
+            Self::Symbolic => LinkStrategy::SymbolicFile,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 #[command(arg_required_else_help = true)]
+#[command(after_help = "Exit codes:
+  0    Everything was linked or skipped as asked
+  1    Some entries failed to link, or a step after linking failed
+  2    Nothing was done because of invalid arguments or a failed check
+  3    --audit found entries of <TARGET> out of sync with <BASE>
+  124  --timeout-total ran out
+  130  The run was stopped early, by cancelling a prompt or by --filter-script")]
 #[allow(clippy::struct_excessive_bools)]
 pub struct Cli {
-    /// Attempts to create a link for each file under base
-    #[arg(value_parser = exists)]
+    /// Attempts to create a link for each file under base. May be a glob pattern (e.g. `packages/*/dist`) matching multiple bases. Left out with --find-root
+    #[arg(value_name = "BASE")]
+    given_base: Option<PathBuf>,
+
+    #[arg(value_name = "TARGET")]
+    /// Target directory to write hardlinks to. When <BASE> is a single file this may also be the path of the link itself
+    given_target: Option<PathBuf>,
+
+    #[arg(long, value_name = "MARKER")]
+    /// Use the closest directory containing MARKER, starting from the current directory and walking up, as <BASE>. Only <TARGET> is given then
+    find_root: Option<PathBuf>,
+
+    /// <BASE> as resolved by `resolve_paths`.
+    #[arg(skip)]
     pub base: PathBuf,
 
-    #[arg(value_parser = is_dir)]
-    /// Target directory to write hardlinks to
+    /// <TARGET> as resolved by `resolve_paths`.
+    #[arg(skip)]
     pub target: PathBuf,
 
+    #[arg(long)]
+    /// Create the link at exactly <TARGET> instead of inside it. Only valid when <BASE> is a single file. Implied when <TARGET> doesn't exist
+    target_is_file: bool,
+
+    #[arg(long, default_value_t = true, action = ArgAction::Set, value_name = "BOOL")]
+    /// Allow <BASE> to be a symbolic link. When false, a symbolic link <BASE> is refused and its real path has to be given instead
+    follow_base_symlink: bool,
+
+    #[arg(long)]
+    /// Canonicalize <BASE> and <TARGET> before starting, so links and messages use absolute paths
+    absolute_base: bool,
+
+    #[arg(long)]
+    /// Link into a fresh `<TARGET>.tmp` and only swap it with <TARGET> once everything succeeded. The previous <TARGET> is kept as `<TARGET>.old`, replacing an earlier one. --index and --resume still record the links but skip nothing, since the staging directory starts out empty
+    atomic_run: bool,
+
+    #[arg(long, conflicts_with_all = ["atomic_run", "resume", "move_source"])]
+    /// List everything the run created once it's done and ask whether to keep it. Answering no removes those links and directories again and puts back what they replaced or backed up
+    confirm_at_end: bool,
+
+    #[arg(long)]
+    /// Refuse to run unless <TARGET> is owned by the current user. Unix only
+    require_owner: bool,
+
+    #[arg(long)]
+    /// Refuse to run instead of warning when <TARGET> is on a filesystem known not to support the links to create, like FAT. Only detected on Linux
+    strict_fs: bool,
+
+    #[arg(long, value_name = "GLOB")]
+    /// Refuse to run if the canonical path of <TARGET> matches GLOB. Can be given multiple times. System directories like / and /usr and the home directory are always forbidden
+    forbid_target: Vec<String>,
+
+    #[arg(long)]
+    /// Allow <TARGET> to be a forbidden directory, see --forbid-target
+    allow_dangerous_target: bool,
+
+    #[arg(long)]
+    /// Don't check that <TARGET> is writable before starting
+    no_writability_check: bool,
+
+    #[arg(long)]
+    /// Check that a link's source still exists before linking it, so no dangling symbolic links get created
+    require_target_exists: bool,
+
     #[arg(short, long)]
     /// Use symbolic links instead of hard links. Will usually fail on Windows since creating symlinks is a privileged action
     symbolic: bool,
 
+    #[arg(long, conflicts_with = "symbolic")]
+    /// Kind of link to create for files (Defaults to hard, or symbolic with --symbolic)
+    file_link: Option<LinkKind>,
+
+    #[arg(long, value_enum)]
+    /// How symbolic links store the path of their source (Defaults to the path as it was found under <BASE>)
+    symlink_policy: Option<SymlinkPolicy>,
+
+    #[arg(long, alias = "symlink-relative-depth-limit", value_name = "N", requires = "symlink_policy")]
+    /// Store an absolute path instead, with a warning, when a relative one from --symlink-policy would have to climb more than N `..` levels
+    relative_max_updepth: Option<usize>,
+
+    #[arg(long, value_name = "FROM=TO", value_parser = prefix_rewrite)]
+    /// Replace the leading FROM of the paths stored in symbolic links with TO, e.g. to point at where <BASE> is mounted in a container. Where the links are placed is unaffected. Links whose path doesn't start with FROM fail
+    retarget_prefix: Option<(PathBuf, PathBuf)>,
+
+    #[arg(long)]
+    /// When a source is itself a symbolic link, point new symbolic links at what it finally resolves to instead of at the source link
+    rebase_symlinks: bool,
+
+    #[arg(long, default_value_t = OnSymlinkPrivilegeError::Abort, value_enum)]
+    /// What to do when Windows refuses to create symbolic links because Developer Mode is off and we aren't elevated
+    on_symlink_privilege_error: OnSymlinkPrivilegeError,
+
+    #[arg(long, alias = "link-function-override", value_name = "STRATEGY")]
+    /// Create file links with STRATEGY, overriding -s and --file-link. Meant for testing and unusual filesystems
+    link_strategy: Option<LinkStrategy>,
+
+    #[arg(long)]
+    /// Copy files that are on a different device than <TARGET> instead of hard linking them, which can't cross devices. Files on the same device are still hard linked
+    auto_copy_cross_device: bool,
+
+    #[arg(long, alias = "target-capacity-estimate")]
+    /// Before linking, add up the size of every file the run will copy and refuse to start if <TARGET> doesn't have that much free space. The estimate is reported even when it fits. Does nothing if no file is copied
+    estimate_copy_size: bool,
+
+    #[arg(long, value_name = "GLOB")]
+    /// Copy files matching GLOB instead of linking them, so edits to either side stay separate. Matched against the file name, or the path relative to <BASE> if GLOB contains a `/`. Can be given multiple times
+    copy_matching: Vec<String>,
+
+    #[arg(long)]
+    /// Delete every source after copying it, once the copy was verified byte for byte. Only files copied with --link-strategy copy or --copy-matching are moved, the ones --auto-copy-cross-device or --on-symlink-privilege-error fall back to copying are kept. Always asks for confirmation first, even with --yes
+    move_source: bool,
+
+    #[arg(long, alias = "retain-extended-attributes")]
+    /// Give copies the extended attributes and ACLs of their source. Only warns where the filesystem doesn't support them. Linux only
+    preserve_xattr: bool,
+
+    #[arg(long)]
+    /// Give copies the permission bits of their source, instead of what copying leaves them with. Only warns if they can't be set
+    preserve_permissions: bool,
+
+    #[arg(long, value_parser = dir_link_kind)]
+    /// Link whole directories instead of recreating them. Only symbolic links can point to directories
+    dir_link: Option<LinkKind>,
+
+    #[arg(long, default_value_t = NoninteractiveFallback::Abort, value_enum)]
+    /// What to do when the run would prompt but there's no terminal to prompt on, e.g. in CI
+    noninteractive_fallback: NoninteractiveFallback,
+
     #[arg(short = 'f', long)]
     /// Always create directories/links, never rename directories/links, always recurse. Each actionn can be overriden by more specific flags
     never_prompt: bool,
@@ -36,62 +264,607 @@ pub struct Cli {
     /// Always create links instead of prompting
     always_create_links: bool,
 
+    #[arg(long)]
+    /// Don't ask whether to create links that already exist and point at their source, skip them instead. Only re-runs with changed or new entries prompt. --force-prompt asks about them anyway
+    skip_prompt_if_linked: bool,
+
+    #[arg(long, alias = "force")]
+    /// Ask about links that already exist and point at their source even with --skip-prompt-if-linked, e.g. when it's set in an alias
+    force_prompt: bool,
+
     #[arg(long)]
     /// How to handle dirs (Defaults to ask)
     create_dirs: Option<Action>,
 
+    #[arg(long)]
+    /// Set the modification time of every directory links were written into to now once it's done
+    touch_target_dirs: bool,
+
+    #[arg(long, requires = "touch_target_dirs")]
+    /// With --touch-target-dirs, use the modification time of the directory's newest entry instead of now
+    touch_from_children: bool,
+
+    #[arg(long, value_name = "TEMPLATE", value_parser = dir_template)]
+    /// Name recreated dirs after TEMPLATE, where `{name}` is the original name and `{lower}` and `{upper}` its lower and upper case forms. Pre-fills the dir rename prompt
+    dir_template: Option<String>,
+
+    #[arg(long, alias = "create-dirs-lazily")]
+    /// Only create recreated directories once the first link goes into them, so directories whose entries are all skipped aren't left behind empty. Prompts for them still come up front
+    lazy_dirs: bool,
+
+    #[arg(long, value_name = "OCTAL", value_parser = octal_mode)]
+    /// Give recreated directories this mode, e.g. 755, regardless of the umask. Unix only
+    dir_permissions: Option<u32>,
+
     #[arg(long)]
     /// Recurse into directories while creating symlinks (Defaults to ask)
     recurse: Option<Action>,
 
+    #[arg(long, alias = "follow-to-depth", value_name = "N")]
+    /// Follow at most N symbolic links on the way from <BASE> to an entry and skip symbolic links beyond that, so untrusted trees can't lead the walk far away. Unlimited by default
+    symlink_follow_depth: Option<usize>,
+
+    #[arg(short = 'x', long)]
+    /// Don't recurse into directories on a different filesystem than <BASE>
+    one_file_system: bool,
+
+    #[arg(long, value_enum)]
+    /// Where files go in <TARGET> (Defaults to mirror). Layouts other than mirror don't recreate directories, so they can't be combined with --merge or orphan handling
+    layout: Option<Layout>,
+
+    #[arg(long, conflicts_with_all = ["on_orphan", "report_orphans", "layout"])]
+    /// Same as `--layout by-ext`
+    group_by_extension: bool,
+
+    #[arg(long, alias = "rename-using-parent-dir", default_value_t = FlattenNaming::Number, value_enum)]
+    /// How files from different directories that get the same name in a --layout other than mirror are told apart. The first one linked keeps its name
+    flatten_naming: FlattenNaming,
+
+    #[arg(long)]
+    /// Read the metadata of every source only once instead of for every check and remember it for the rest of the run. Uses more memory on large trees
+    inode_cache: bool,
+
+    #[arg(long, value_name = "N")]
+    /// Keep at most N directory handles open while recursing, counting those of every --parallel-dirs worker. Deeper directories are read into memory up front
+    max_open_dirs: Option<NonZeroUsize>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["index", "resume", "log_file", "lazy_dirs", "batch_size", "checkpoint_every"]
+    )]
+    /// Walk up to N sibling directories at once, each in its own order. Their output is held back and printed in walk order. Can't be combined with prompts or with --layout
+    parallel_dirs: Option<NonZeroUsize>,
+
+    #[arg(long, value_name = "N")]
+    /// Link at most N files from any single directory, skipping the rest of its files with a warning. Subdirectories are still processed
+    per_dir_limit: Option<NonZeroUsize>,
+
+    #[arg(long, default_value_t = OnPermissionDenied::Warn, value_enum)]
+    /// What to do when a directory or entry in <BASE> can't be read because permission was denied
+    on_permission_denied: OnPermissionDenied,
+
     #[arg(long)]
     /// Prompt the user for a new name for a dir. This is the default behaviour and this flag is only usefull to override --never-prompt
     ask_to_rename_dirs: bool,
 
+    #[arg(long, conflicts_with = "ask_to_rename_dirs")]
+    /// Whether to prompt for a new name for recreated dirs, independently of the creation prompt. Ask asks whether to rename first (Defaults to always unless --never-prompt is given)
+    confirm_dir_name: Option<Action>,
+
+    #[arg(long, value_name = "KEY")]
+    /// Name links after the slugified value of KEY in the YAML frontmatter of their source, keeping the extension. Sources without it keep their name. --name-map takes precedence
+    name_from_frontmatter: Option<String>,
+
+    #[arg(long, value_name = "FROM=TO", value_parser = extension_rewrite)]
+    /// Give links to files ending in the extension FROM the extension TO instead. Can be given multiple times. Applied after --name-map and before --max-name-length
+    rewrite_ext: Vec<(String, String)>,
+
+    #[arg(long)]
+    /// Replace characters link names can't contain with --sanitize-replacement: control characters, and on Windows, FAT and SMB also `<>:"\|?*`. Applied before --max-name-length
+    sanitize_names: bool,
+
+    #[arg(long, value_name = "CHAR", default_value_t = '_', value_parser = sanitize_replacement, requires = "sanitize_names")]
+    /// Character --sanitize-names replaces others with
+    sanitize_replacement: char,
+
+    #[arg(long, value_name = "N", value_parser = name_length)]
+    /// Shorten link names longer than N bytes, keeping the extension and appending a short hash of the full name
+    max_name_length: Option<usize>,
+
+    #[arg(long)]
+    /// Offer skipping the rest of the current directory in link creation prompts, which become a list to pick from with the arrow keys
+    interactive_skip_key: bool,
+
+    #[arg(long)]
+    /// Read each directory up front and pick which of its files to link from one list with checkboxes, instead of being asked about every file
+    multiselect_per_dir: bool,
+
+    #[arg(long, default_value_t = WithinDirOrder::Read, value_enum)]
+    /// Order to handle the entries of a directory in, e.g. to answer the prompts for a directory's files before descending. Reads each directory up front unless it's `read`
+    within_dir_order: WithinDirOrder,
+
+    #[arg(long)]
+    /// Show the size of the source in link creation prompts
+    prompt_show_sizes: bool,
+
     #[arg(long)]
     /// Prompt the user for a new name for a link. This is the default behaviour and this flag is only usefull to override --never-prompt
     ask_to_rename_links: bool,
 
+    #[arg(long)]
+    /// Only prompt for a new link name when a link with the computed name already exists, even with --never-prompt. Keeping the name leaves the existing file to --on-conflict
+    rename_on_conflict_only: bool,
+
+    #[arg(long)]
+    /// Replace existing symbolic links that dangle or point at something other than their source, and skip correct ones. Other existing destinations are still handled by --on-conflict
+    refresh_stale: bool,
+
+    #[arg(long, conflicts_with = "group_by_extension")]
+    /// Overlay <BASE> onto a populated <TARGET>: enter directories that already exist without prompting and skip destinations that already link to their source. Other existing destinations are still handled by --on-conflict
+    merge: bool,
+
+    #[arg(long, default_value_t = OnConflict::Error, value_enum)]
+    /// What to do when a link's destination already exists
+    on_conflict: OnConflict,
+
+    #[arg(long)]
+    /// Only overwrite an existing destination if its content is identical to the source. Only has an effect with `--on-conflict overwrite`
+    hardlink_identical_only: bool,
+
+    #[arg(long, alias = "exclude-newer-than-target")]
+    /// Don't overwrite an existing destination that is a separate file modified after its source, it was most likely edited in <TARGET>. Only has an effect with `--on-conflict overwrite` or `newer`
+    protect_modified_target: bool,
+
+    #[arg(long, value_name = "PATH")]
+    /// With `--on-conflict backup`, move displaced destinations into PATH, keeping their path relative to <TARGET>
+    backup_dir: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Write a `<link>.meta` sidecar next to every link recording its source and when the run started. Failing to write one only warns
+    write_provenance: bool,
+
+    #[arg(long, default_value_t = ProvenanceFormat::Text, value_enum, requires = "write_provenance")]
+    /// Format of --write-provenance sidecars
+    provenance_format: ProvenanceFormat,
+
+    #[arg(long, value_name = "DIR", requires = "write_provenance")]
+    /// Put --write-provenance sidecars under DIR, at the path of their link relative to <TARGET>, instead of next to the links
+    provenance_dir: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Always ask before overwriting an existing destination, even with --never-prompt
+    confirm_overwrite: bool,
+
+    #[arg(long)]
+    /// Default create/recurse prompts to the answer you gave the last 3 times in a row
+    adaptive_defaults: bool,
+
+    #[arg(long)]
+    /// Print a line for every entry saying why it was linked, skipped or prompted for and which flag decided it. Implied by -vv
+    explain: bool,
+
+    #[arg(short, long, action = ArgAction::Count)]
+    /// Print more about what's happening. Repeat for even more detail, -vv also explains every decision like --explain
+    verbose: u8,
+
+    #[arg(long, value_name = "N")]
+    /// Count the run first and don't prompt at all if it would create fewer than N links, as if -f and -y were given
+    auto_confirm_under: Option<u64>,
+
+    #[arg(short, long)]
+    /// Answer yes to confirmations that --never-prompt doesn't cover, like --confirm-overwrite
+    yes: bool,
+
+    #[arg(long)]
+    /// After linking, list entries in <TARGET> that don't exist at the same path in <BASE>. Same as `--on-orphan report`
+    report_orphans: bool,
+
+    #[arg(long, conflicts_with = "report_orphans")]
+    /// What to do with entries in <TARGET> that don't exist at the same path in <BASE> (Defaults to ignore)
+    on_orphan: Option<OnOrphan>,
+
+    #[arg(long)]
+    /// With `--on-orphan remove`, also delete orphaned regular files and directories instead of only links
+    remove_orphan_files: bool,
+
+    #[arg(long, value_name = "FILE")]
+    /// Name links after FILE instead of their source. Each line is `source<TAB>name` with `source` relative to <BASE>
+    name_map: Option<PathBuf>,
+
+    #[arg(long, alias = "prompt-history-file", value_name = "FILE")]
+    /// Remember the answer to every link creation prompt in FILE and give it again without asking in later runs. Delete a source's line from FILE to be asked again
+    remember_answers: Option<PathBuf>,
+
+    #[arg(long, requires = "remember_answers")]
+    /// Ask again about every source instead of using the answers in --remember-answers, replacing them
+    forget: bool,
+
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "1000")]
+    /// Print the counts so far to stderr every N entries, 1000 if N is left out
+    batch_size: Option<NonZeroU64>,
+
+    #[arg(long, alias = "checkpoint-interval", value_name = "N", conflicts_with = "confirm_at_end")]
+    /// Save the --index and flush the --resume file to disk every N entries instead of only at the end, so a crash loses little progress
+    checkpoint_every: Option<NonZeroU64>,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// Stop the run once it has taken longer than SECONDS. Exits with code 124 when that happens
+    timeout_total: Option<u64>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Remember linked sources in FILE and skip them on later runs until they're modified
+    index: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Append a timestamped line for every link and directory created, skipped or failed to FILE as it happens
+    log_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "index")]
+    /// Write every link to FILE as soon as it's created and skip sources whose recorded link is still intact, so an interrupted run can be continued
+    resume: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Write the sources that failed to link, or were still being written with --require-stable, to FILE, one per line, so they can be retried
+    failures_file: Option<PathBuf>,
+
+    #[arg(long, requires = "failures_file")]
+    /// Terminate the paths in --failures-file with NUL instead of newlines
+    failures_nul: bool,
+
+    #[arg(long, default_value_t = ReportFormat::Text, value_enum)]
+    /// Format of the summary printed at the end of a run
+    report_format: ReportFormat,
+
+    #[arg(long)]
+    /// Break the links created down by the extension of their source in the summary, with the total size of each
+    summary_by_ext: bool,
+
+    #[arg(long, alias = "report-bytes-linked")]
+    /// Add the total size of the files that were linked to the summary. The links themselves take up far less space
+    report_bytes: bool,
+
+    #[arg(long)]
+    /// Don't link empty files
+    skip_zero_byte_files: bool,
+
+    #[arg(long)]
+    /// Don't link files that are already hard linked somewhere else, assuming they're managed elsewhere. Unix only
+    skip_multilinked: bool,
+
+    #[arg(long, value_name = "AGE", value_parser = age)]
+    /// Only link files last modified at least AGE ago. AGE is a number followed by s, m, h, d, w or y, e.g. 30d
+    min_age: Option<Duration>,
+
+    #[arg(long, value_name = "AGE", value_parser = age)]
+    /// Only link files last modified at most AGE ago, see --min-age
+    max_age: Option<Duration>,
+
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u64).range(2..))]
+    /// With --skip-multilinked, skip files with at least N hard links
+    multilink_threshold: u64,
+
+    #[arg(long, alias = "link-only-if-source-stable", value_name = "MS")]
+    /// Only link files whose size and modification time stay the same over MS milliseconds, skipping files other processes are still writing. Waits MS for every file
+    require_stable: Option<u64>,
+
+    #[arg(long, value_parser = ShellCommand::parse, value_name = "CMD")]
+    /// Only link files for which CMD, run with the file's path appended, exits with 0. Exiting with 255 aborts the run. Quoted like --pre-hook. This starts a process per file, so prefer filtering once up front for large trees. --auto-confirm-under and --estimate-copy-size count the files before the run starts, which runs CMD a second time for every file
+    filter_script: Option<ShellCommand>,
+
+    #[arg(long, value_parser = ShellCommand::parse)]
+    /// Command to run before linking, a nonzero exit aborts the run. Split with shell quoting rules but not run by a shell, use `sh -c '...'` for pipes and redirections
+    pre_hook: Option<ShellCommand>,
+
+    #[arg(long, value_parser = ShellCommand::parse)]
+    /// Command to run after linking. Receives the summary counts in `TLU_*` environment variables (e.g. `TLU_LINKS_CREATED`). Quoted like --pre-hook
+    post_hook: Option<ShellCommand>,
+
+    #[arg(long)]
+    /// Only count how many links and directories would be created, without prompting or touching <TARGET>
+    count_only: bool,
+
+    #[arg(long, conflicts_with = "count_only")]
+    /// Only compare <TARGET> with <BASE> and list sources missing their link, destinations not linking to their source and entries without a source, without prompting or touching <TARGET>. Uses --report-format
+    audit: bool,
+
+    #[arg(long, value_name = "MANIFEST", alias = "link-plan-diff", conflicts_with_all = ["count_only", "audit"])]
+    /// Only compare the links a run would create with the ones the earlier run that wrote the --index file MANIFEST recorded, and list the added, removed and unchanged ones sorted by destination, without prompting or touching <TARGET>. Uses --report-format
+    diff_against: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Print the effective value of every setting, after defaults and overriding flags are applied, and exit
+    dump_config: bool,
+
+    #[arg(long)]
+    /// Show paths in messages relative to the base or <TARGET> they're in. Only affects output, not links
+    relative_output: bool,
+
+    /// Bases paths in messages are shown relative to with --relative-output.
+    #[arg(skip)]
+    output_bases: Vec<PathBuf>,
+
     #[arg(long)]
     /// Generate completions, print the to stdout, exit.
     completions: Option<Shell>,
 }
 
-fn exists(path: &str) -> Result<PathBuf, String> {
-    let buf = PathBuf::from(path);
-
-    if !buf.exists() {
+fn exists(path: PathBuf) -> Result<PathBuf, String> {
+    if !path.exists() && !glob::is_pattern(&path.to_string_lossy()) {
         return Err("<BASE> path doesn't exist!".to_string());
     }
 
-    Ok(buf)
+    Ok(path)
 }
 
-fn is_dir(path: &str) -> Result<PathBuf, String> {
-    let path = PathBuf::from(path);
+/// Walks up from the current directory to the first directory containing `marker`.
+fn find_root(marker: &Path) -> Result<PathBuf, String> {
+    let current = env::current_dir().map_err(|err| format!("Failed to get the current directory: {err}"))?;
 
-    let metadata = path
-        .metadata()
-        .map_err(|err| format!("Can't open <TARGET> directory: {err}"))?;
-    if metadata.is_dir() {
-        Ok(path)
-    } else {
-        Err("<TARGET> is not a directory!".to_string())
+    current
+        .ancestors()
+        .find(|dir| dir.join(marker).symlink_metadata().is_ok())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| {
+            format!(
+                "No `{}` found in `{}` or any of its parents",
+                marker.display(),
+                current.display()
+            )
+        })
+}
+
+fn dir_link_kind(kind: &str) -> Result<LinkKind, String> {
+    match LinkKind::from_str(kind, true)? {
+        LinkKind::Hard => Err("directories can't be hardlinked".to_string()),
+        kind @ LinkKind::Symbolic => Ok(kind),
     }
 }
 
-impl Cli {
-    pub const fn link_function<P: AsRef<Path>, Q: AsRef<Path>>(&self) -> fn(P, Q) -> io::Result<()> {
-        #[cfg(target_family = "unix")]
-        if self.symbolic() {
-            return std::os::unix::fs::symlink;
+fn dir_template(template: &str) -> Result<String, String> {
+    names::render_template(template, "")?;
+    Ok(template.to_string())
+}
+
+fn octal_mode(mode: &str) -> Result<u32, String> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    let mode = u32::from_str_radix(digits, 8).map_err(|err| format!("not an octal mode: {err}"))?;
+    if mode > 0o7777 {
+        return Err("modes only go up to 7777".to_string());
+    }
+
+    Ok(mode)
+}
+
+fn extension_rewrite(rewrite: &str) -> Result<(String, String), String> {
+    let (from, to) = rewrite.split_once('=').ok_or_else(|| "expected `FROM=TO`".to_string())?;
+    let from = from.strip_prefix('.').unwrap_or(from);
+    let to = to.strip_prefix('.').unwrap_or(to);
+    if from.is_empty() {
+        return Err("the extension to rewrite can't be empty".to_string());
+    }
+    // An empty `to` drops the extension.
+    for extension in [from, to] {
+        if extension.contains('.') || extension.chars().any(std::path::is_separator) {
+            return Err(format!("`{extension}` isn't a single extension"));
+        }
+    }
+
+    Ok((from.to_string(), to.to_string()))
+}
+
+fn age(age: &str) -> Result<Duration, String> {
+    const UNITS: [(char, u64); 6] = [
+        ('s', 1),
+        ('m', 60),
+        ('h', 60 * 60),
+        ('d', 24 * 60 * 60),
+        ('w', 7 * 24 * 60 * 60),
+        ('y', 365 * 24 * 60 * 60),
+    ];
+
+    let unit = age.chars().last().ok_or_else(|| "empty age".to_string())?;
+    let (_, seconds) = UNITS
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .ok_or_else(|| "expected a number followed by s, m, h, d, w or y".to_string())?;
+    let count: u64 = age[..age.len() - unit.len_utf8()].parse().map_err(|err| format!("not an age: {err}"))?;
+
+    count
+        .checked_mul(*seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| "age is too large".to_string())
+}
+
+fn prefix_rewrite(rewrite: &str) -> Result<(PathBuf, PathBuf), String> {
+    let (from, to) = rewrite.split_once('=').ok_or_else(|| "expected `FROM=TO`".to_string())?;
+    if from.is_empty() {
+        return Err("the prefix to replace can't be empty".to_string());
+    }
+
+    Ok((PathBuf::from(from), PathBuf::from(to)))
+}
+
+fn sanitize_replacement(replacement: &str) -> Result<char, String> {
+    let mut chars = replacement.chars();
+    let (Some(replacement), None) = (chars.next(), chars.next()) else {
+        return Err("the replacement has to be a single character".to_string());
+    };
+    if !names::is_allowed(replacement, true) {
+        return Err(format!("`{replacement}` can't be used in names itself"));
+    }
+
+    Ok(replacement)
+}
+
+fn name_length(length: &str) -> Result<usize, String> {
+    // Leaves room for the hash `names::truncate` appends and a few characters of the name.
+    const MIN: usize = 16;
+
+    let length: usize = length.parse().map_err(|err| format!("not a length: {err}"))?;
+    if length < MIN {
+        return Err(format!("names can't be limited to less than {MIN} bytes"));
+    }
+
+    Ok(length)
+}
+
+fn target(path: PathBuf) -> Result<PathBuf, String> {
+    match path.metadata() {
+        // Whether a file is an acceptable <TARGET> depends on <BASE>, main checks that.
+        Ok(_) => Ok(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+
+            if parent.is_dir() {
+                Ok(path)
+            } else {
+                Err("<TARGET> doesn't exist and neither does its parent directory!".to_string())
+            }
         }
-        #[cfg(target_family = "windows")]
-        if self.symbolic() {
-            return std::os::windows::fs::symlink_file;
+        Err(err) => Err(format!("Can't open <TARGET>: {err}")),
+    }
+}
+
+impl Cli {
+    /// Checks whether `path` is the `--find-root` marker of <BASE>, which isn't linked itself.
+    pub fn is_root_marker(&self, path: &Path) -> bool {
+        self.find_root.as_ref().is_some_and(|marker| path == self.base.join(marker))
+    }
+
+    /// Checks <BASE> and <TARGET> as given on the command line and fills them in, finding <BASE> first with
+    /// `--find-root`. Has to be called before anything else looks at them.
+    pub fn resolve_paths(&mut self) -> Result<(), String> {
+        let (base, target_path) = match (&self.find_root, self.given_base.take(), self.given_target.take()) {
+            (None, Some(base), Some(target)) => (base, target),
+            // With --find-root the only path given is <TARGET>.
+            (Some(marker), Some(target), None) => (find_root(marker)?, target),
+            (Some(_), Some(_), Some(_)) => return Err("<BASE> can't be given together with --find-root".to_string()),
+            (None, _, None) | (Some(_), None, _) => return Err("<TARGET> is required".to_string()),
+            (None, None, Some(_)) => return Err("<BASE> is required unless --find-root is given".to_string()),
+        };
+
+        self.base = exists(base)?;
+        self.target = target(target_path)?;
+        Ok(())
+    }
+
+    pub fn file_link(&self) -> LinkKind {
+        self.file_link.unwrap_or(if self.symbolic() {
+            LinkKind::Symbolic
+        } else {
+            LinkKind::Hard
+        })
+    }
+
+    pub fn link_strategy(&self) -> LinkStrategy {
+        self.link_strategy.unwrap_or_else(|| self.file_link().file_strategy())
+    }
+
+    /// Whether `--copy-matching` copies anything.
+    pub fn copies_any(&self) -> bool {
+        !self.copy_matching.is_empty()
+    }
+
+    pub const fn auto_copy_cross_device(&self) -> bool {
+        self.auto_copy_cross_device
+    }
+
+    pub const fn estimate_copy_size(&self) -> bool {
+        self.estimate_copy_size
+    }
+
+    /// Whether the run may copy files, with `--link-strategy copy`, `--copy-matching` or one of the fallbacks to
+    /// copying.
+    pub fn may_copy(&self) -> bool {
+        let strategy = self.link_strategy();
+        strategy == LinkStrategy::Copy
+            || self.copies_any()
+            || (self.auto_copy_cross_device && strategy == LinkStrategy::Hard)
+            || (cfg!(target_family = "windows")
+                && strategy.is_symbolic()
+                && self.on_symlink_privilege_error == OnSymlinkPrivilegeError::Copy)
+    }
+
+    pub const fn preserve_xattr(&self) -> bool {
+        self.preserve_xattr
+    }
+
+    pub const fn preserve_permissions(&self) -> bool {
+        self.preserve_permissions
+    }
+
+    pub const fn move_source(&self) -> bool {
+        self.move_source
+    }
+
+    /// Strategy to link the file at `relative`, a path relative to <BASE>, with. `--copy-matching` takes precedence over
+    /// the link mode.
+    pub fn file_strategy(&self, relative: &Path) -> LinkStrategy {
+        let name = relative.file_name().map(|name| name.to_string_lossy());
+        let relative = relative.to_string_lossy();
+        let copy = self.copy_matching.iter().any(|pattern| {
+            if pattern.contains('/') {
+                glob::matches(pattern, &relative)
+            } else {
+                name.as_deref().is_some_and(|name| glob::matches(pattern, name))
+            }
+        });
+
+        if copy {
+            LinkStrategy::Copy
+        } else {
+            self.link_strategy()
         }
+    }
+
+    pub const fn symlink_policy(&self) -> Option<SymlinkPolicy> {
+        self.symlink_policy
+    }
+
+    pub const fn relative_max_updepth(&self) -> Option<usize> {
+        self.relative_max_updepth
+    }
+
+    pub fn retarget_prefix(&self) -> Option<(&Path, &Path)> {
+        self.retarget_prefix.as_ref().map(|(from, to)| (from.as_path(), to.as_path()))
+    }
+
+    pub const fn rebase_symlinks(&self) -> bool {
+        self.rebase_symlinks
+    }
+
+    pub const fn on_symlink_privilege_error(&self) -> OnSymlinkPrivilegeError {
+        self.on_symlink_privilege_error
+    }
+
+    /// Strategy to link whole directories with. `None` if directories should be recreated instead.
+    pub fn dir_link_strategy(&self) -> Option<LinkStrategy> {
+        // Directories can't be hardlinked, `dir_link_kind` rejects that combination.
+        self.dir_link.map(|_| LinkStrategy::SymbolicDir)
+    }
+
+    pub const fn touch_target_dirs(&self) -> bool {
+        self.touch_target_dirs
+    }
+
+    pub const fn touch_from_children(&self) -> bool {
+        self.touch_from_children
+    }
+
+    pub const fn lazy_dirs(&self) -> bool {
+        self.lazy_dirs
+    }
 
-        fs::hard_link
+    pub fn dir_template(&self) -> Option<&str> {
+        self.dir_template.as_deref()
+    }
+
+    pub const fn dir_permissions(&self) -> Option<u32> {
+        self.dir_permissions
     }
 
     pub fn recurse(&self) -> Action {
@@ -102,6 +875,46 @@ impl Cli {
         })
     }
 
+    pub const fn symlink_follow_depth(&self) -> Option<usize> {
+        self.symlink_follow_depth
+    }
+
+    pub const fn one_file_system(&self) -> bool {
+        self.one_file_system
+    }
+
+    pub fn layout(&self) -> Layout {
+        if self.group_by_extension {
+            Layout::ByExt
+        } else {
+            self.layout.unwrap_or(Layout::Mirror)
+        }
+    }
+
+    pub const fn flatten_naming(&self) -> FlattenNaming {
+        self.flatten_naming
+    }
+
+    pub fn per_dir_limit(&self) -> Option<usize> {
+        self.per_dir_limit.map(NonZeroUsize::get)
+    }
+
+    pub const fn inode_cache(&self) -> bool {
+        self.inode_cache
+    }
+
+    pub fn max_open_dirs(&self) -> Option<usize> {
+        self.max_open_dirs.map(NonZeroUsize::get)
+    }
+
+    pub fn parallel_dirs(&self) -> Option<usize> {
+        self.parallel_dirs.map(NonZeroUsize::get)
+    }
+
+    pub const fn on_permission_denied(&self) -> OnPermissionDenied {
+        self.on_permission_denied
+    }
+
     pub fn create_dirs(&self) -> Action {
         self.create_dirs.unwrap_or(if self.never_prompt {
             Action::Always
@@ -110,8 +923,16 @@ impl Cli {
         })
     }
 
-    pub const fn ask_to_rename_dirs(&self) -> bool {
-        !self.never_prompt || self.ask_to_rename_dirs
+    pub const fn confirm_dir_name(&self) -> Action {
+        match self.confirm_dir_name {
+            Some(action) => action,
+            None if !self.never_prompt || self.ask_to_rename_dirs => Action::Always,
+            None => Action::Never,
+        }
+    }
+
+    pub const fn skip_prompt_if_linked(&self) -> bool {
+        self.skip_prompt_if_linked && !self.force_prompt
     }
 
     pub const fn create_links(&self) -> Action {
@@ -122,14 +943,475 @@ impl Cli {
         }
     }
 
-    pub const fn ask_to_rename_links(&self) -> bool {
-        !self.never_prompt || self.ask_to_rename_links
+    pub fn name_from_frontmatter(&self) -> Option<&str> {
+        self.name_from_frontmatter.as_deref()
+    }
+
+    pub fn rewrite_ext(&self) -> &[(String, String)] {
+        &self.rewrite_ext
+    }
+
+    pub const fn sanitize_names(&self) -> bool {
+        self.sanitize_names
+    }
+
+    pub const fn sanitize_replacement(&self) -> char {
+        self.sanitize_replacement
+    }
+
+    pub const fn max_name_length(&self) -> Option<usize> {
+        self.max_name_length
+    }
+
+    pub const fn interactive_skip_key(&self) -> bool {
+        self.interactive_skip_key
+    }
+
+    pub const fn multiselect_per_dir(&self) -> bool {
+        self.multiselect_per_dir
+    }
+
+    pub const fn within_dir_order(&self) -> WithinDirOrder {
+        self.within_dir_order
+    }
+
+    pub const fn prompt_show_sizes(&self) -> bool {
+        self.prompt_show_sizes
+    }
+
+    /// Whether to prompt for a new link name. `link_exists` tells whether a link with the computed name already exists.
+    pub const fn ask_to_rename_links(&self, link_exists: bool) -> bool {
+        if self.rename_on_conflict_only {
+            link_exists
+        } else {
+            !self.never_prompt || self.ask_to_rename_links
+        }
     }
 
     pub const fn symbolic(&self) -> bool {
         self.symbolic
     }
 
+    pub const fn target_is_file(&self) -> bool {
+        self.target_is_file
+    }
+
+    /// Directory links will be written into. Either <TARGET> or its parent when linking to an exact file path.
+    pub fn target_dir(&self) -> &Path {
+        if self.target.is_dir() {
+            return &self.target;
+        }
+
+        match self.target.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    }
+
+    pub const fn follow_base_symlink(&self) -> bool {
+        self.follow_base_symlink
+    }
+
+    pub const fn absolute_base(&self) -> bool {
+        self.absolute_base
+    }
+
+    pub const fn atomic_run(&self) -> bool {
+        self.atomic_run
+    }
+
+    pub const fn strict_fs(&self) -> bool {
+        self.strict_fs
+    }
+
+    pub fn forbid_target(&self) -> &[String] {
+        &self.forbid_target
+    }
+
+    pub const fn allow_dangerous_target(&self) -> bool {
+        self.allow_dangerous_target
+    }
+
+    pub const fn confirm_at_end(&self) -> bool {
+        self.confirm_at_end
+    }
+
+    pub const fn require_owner(&self) -> bool {
+        self.require_owner
+    }
+
+    pub const fn no_writability_check(&self) -> bool {
+        self.no_writability_check
+    }
+
+    pub const fn require_target_exists(&self) -> bool {
+        self.require_target_exists
+    }
+
+    pub const fn refresh_stale(&self) -> bool {
+        self.refresh_stale
+    }
+
+    pub const fn merge(&self) -> bool {
+        self.merge
+    }
+
+    pub const fn on_conflict(&self) -> OnConflict {
+        self.on_conflict
+    }
+
+    pub const fn hardlink_identical_only(&self) -> bool {
+        self.hardlink_identical_only
+    }
+
+    pub const fn protect_modified_target(&self) -> bool {
+        self.protect_modified_target
+    }
+
+    pub fn backup_dir(&self) -> Option<&Path> {
+        self.backup_dir.as_deref()
+    }
+
+    pub const fn write_provenance(&self) -> bool {
+        self.write_provenance
+    }
+
+    pub const fn provenance_format(&self) -> ProvenanceFormat {
+        self.provenance_format
+    }
+
+    pub fn provenance_dir(&self) -> Option<&Path> {
+        self.provenance_dir.as_deref()
+    }
+
+    pub const fn confirm_overwrite(&self) -> bool {
+        self.confirm_overwrite && !self.yes
+    }
+
+    pub fn on_orphan(&self) -> OnOrphan {
+        self.on_orphan.unwrap_or(if self.report_orphans {
+            OnOrphan::Report
+        } else {
+            OnOrphan::Ignore
+        })
+    }
+
+    pub const fn remove_orphan_files(&self) -> bool {
+        self.remove_orphan_files
+    }
+
+    /// Number of links below which `--auto-confirm-under` stops all prompting.
+    pub const fn auto_confirm_under(&self) -> Option<u64> {
+        self.auto_confirm_under
+    }
+
+    pub const fn noninteractive_fallback(&self) -> NoninteractiveFallback {
+        self.noninteractive_fallback
+    }
+
+    /// Whether the run may show a prompt at all.
+    pub fn may_prompt(&self) -> bool {
+        self.create_links() == Action::Ask
+            || self.create_dirs() == Action::Ask
+            || self.recurse() == Action::Ask
+            || self.confirm_dir_name() != Action::Never
+            || self.ask_to_rename_links(true)
+            || self.confirm_overwrite()
+            || (self.on_orphan() == OnOrphan::Remove && !self.assume_yes())
+            || self.confirm_at_end
+    }
+
+    /// Stops all prompting, like -f and -y do. Used by --auto-confirm-under.
+    pub fn confirm_everything(&mut self) {
+        self.never_prompt = true;
+        self.yes = true;
+        self.rename_on_conflict_only = false;
+    }
+
+    /// Whether confirmations should be skipped because of --never-prompt or --yes.
+    pub const fn assume_yes(&self) -> bool {
+        self.never_prompt || self.yes
+    }
+
+    pub fn remember_answers(&self) -> Option<&Path> {
+        self.remember_answers.as_deref()
+    }
+
+    pub const fn forget(&self) -> bool {
+        self.forget
+    }
+
+    pub fn name_map(&self) -> Option<&Path> {
+        self.name_map.as_deref()
+    }
+
+    pub const fn min_age(&self) -> Option<Duration> {
+        self.min_age
+    }
+
+    pub const fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Checks whether a file last modified at `modified` is within `--min-age` and `--max-age`. Files modified in the
+    /// future count as brand new.
+    pub fn within_age(&self, modified: SystemTime) -> bool {
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        self.min_age.is_none_or(|min| age >= min) && self.max_age.is_none_or(|max| age <= max)
+    }
+
+    pub fn batch_size(&self) -> Option<u64> {
+        self.batch_size.map(NonZeroU64::get)
+    }
+
+    pub fn checkpoint_every(&self) -> Option<u64> {
+        self.checkpoint_every.map(NonZeroU64::get)
+    }
+
+    pub fn require_stable(&self) -> Option<Duration> {
+        self.require_stable.map(Duration::from_millis)
+    }
+
+    pub fn timeout_total(&self) -> Option<Duration> {
+        self.timeout_total.map(Duration::from_secs)
+    }
+
+    pub fn index(&self) -> Option<&Path> {
+        self.index.as_deref()
+    }
+
+    pub fn log_file(&self) -> Option<&Path> {
+        self.log_file.as_deref()
+    }
+
+    pub fn resume(&self) -> Option<&Path> {
+        self.resume.as_deref()
+    }
+
+    pub fn failures_file(&self) -> Option<&Path> {
+        self.failures_file.as_deref()
+    }
+
+    pub const fn failures_nul(&self) -> bool {
+        self.failures_nul
+    }
+
+    pub const fn report_format(&self) -> ReportFormat {
+        self.report_format
+    }
+
+    pub const fn summary_by_ext(&self) -> bool {
+        self.summary_by_ext
+    }
+
+    pub const fn report_bytes(&self) -> bool {
+        self.report_bytes
+    }
+
+    pub const fn skip_zero_byte_files(&self) -> bool {
+        self.skip_zero_byte_files
+    }
+
+    /// Hard link count from which files are skipped. `None` unless --skip-multilinked is given.
+    pub const fn multilink_threshold(&self) -> Option<u64> {
+        if self.skip_multilinked {
+            Some(self.multilink_threshold)
+        } else {
+            None
+        }
+    }
+
+    pub const fn filter_script(&self) -> Option<&ShellCommand> {
+        self.filter_script.as_ref()
+    }
+
+    pub const fn count_only(&self) -> bool {
+        self.count_only
+    }
+
+    pub const fn audit(&self) -> bool {
+        self.audit
+    }
+
+    pub fn diff_against(&self) -> Option<&Path> {
+        self.diff_against.as_deref()
+    }
+
+    /// Whether the run may change <TARGET>, which `--count-only`, `--audit` and `--diff-against` don't.
+    pub const fn writes_target(&self) -> bool {
+        !self.count_only && !self.audit && self.diff_against.is_none()
+    }
+
+    pub const fn pre_hook(&self) -> Option<&ShellCommand> {
+        self.pre_hook.as_ref()
+    }
+
+    pub const fn post_hook(&self) -> Option<&ShellCommand> {
+        self.post_hook.as_ref()
+    }
+
+    /// Whether to explain every decision, with `--explain` or `-vv`.
+    pub const fn explain(&self) -> bool {
+        self.explain || self.verbose >= 2
+    }
+
+    pub const fn verbose(&self) -> u8 {
+        self.verbose
+    }
+
+    pub const fn adaptive_defaults(&self) -> bool {
+        self.adaptive_defaults
+    }
+
+    /// Sets the resolved bases for --relative-output. A base that is a single file counts as its directory.
+    pub fn set_output_bases(&mut self, bases: &[PathBuf]) {
+        self.output_bases = bases
+            .iter()
+            .map(|base| match base.parent() {
+                Some(parent) if base.is_file() => parent.to_path_buf(),
+                _ => base.clone(),
+            })
+            .collect();
+    }
+
+    /// Formats `path` for messages. With --relative-output it's shown relative to the closest base or <TARGET>.
+    pub fn show<'a>(&self, path: &'a Path) -> path::Display<'a> {
+        if !self.relative_output {
+            return path.display();
+        }
+
+        let relative = self
+            .output_bases
+            .iter()
+            .chain(iter::once(&self.target))
+            .filter_map(|root| path.strip_prefix(root).ok())
+            .min_by_key(|relative| relative.as_os_str().len());
+        match relative {
+            Some(relative) if relative.as_os_str().is_empty() => Path::new(".").display(),
+            Some(relative) => relative.display(),
+            None => path.display(),
+        }
+    }
+
+    pub const fn dump_config(&self) -> bool {
+        self.dump_config
+    }
+
+    /// The effective value of every setting, one `name: value` line each.
+    #[allow(clippy::too_many_lines)] // One line per setting.
+    pub fn resolved_config(&self) -> String {
+        let settings = [
+            ("base", self.base.display().to_string()),
+            ("target", self.target.display().to_string()),
+            ("find-root", format!("{:?}", self.find_root)),
+            ("target-is-file", format!("{:?}", self.target_is_file())),
+            ("follow-base-symlink", format!("{:?}", self.follow_base_symlink())),
+            ("absolute-base", format!("{:?}", self.absolute_base())),
+            ("atomic-run", format!("{:?}", self.atomic_run())),
+            ("confirm-at-end", format!("{:?}", self.confirm_at_end())),
+            ("require-owner", format!("{:?}", self.require_owner())),
+            ("strict-fs", format!("{:?}", self.strict_fs())),
+            ("forbid-target", format!("{:?}", self.forbid_target())),
+            ("allow-dangerous-target", format!("{:?}", self.allow_dangerous_target())),
+            ("writability-check", format!("{:?}", !self.no_writability_check())),
+            ("require-target-exists", format!("{:?}", self.require_target_exists())),
+            ("copy-matching", format!("{:?}", self.copy_matching)),
+            ("auto-copy-cross-device", format!("{:?}", self.auto_copy_cross_device())),
+            ("estimate-copy-size", format!("{:?}", self.estimate_copy_size())),
+            ("move-source", format!("{:?}", self.move_source())),
+            ("preserve-xattr", format!("{:?}", self.preserve_xattr())),
+            ("preserve-permissions", format!("{:?}", self.preserve_permissions())),
+            ("link-strategy", format!("{:?}", self.link_strategy())),
+            ("dir-link-strategy", format!("{:?}", self.dir_link_strategy())),
+            ("symlink-policy", format!("{:?}", self.symlink_policy())),
+            ("relative-max-updepth", format!("{:?}", self.relative_max_updepth())),
+            ("retarget-prefix", format!("{:?}", self.retarget_prefix())),
+            ("rebase-symlinks", format!("{:?}", self.rebase_symlinks())),
+            ("on-symlink-privilege-error", format!("{:?}", self.on_symlink_privilege_error())),
+            ("create-links", format!("{:?}", self.create_links())),
+            ("skip-prompt-if-linked", format!("{:?}", self.skip_prompt_if_linked())),
+            ("force-prompt", format!("{:?}", self.force_prompt)),
+            ("create-dirs", format!("{:?}", self.create_dirs())),
+            ("touch-target-dirs", format!("{:?}", self.touch_target_dirs())),
+            ("touch-from-children", format!("{:?}", self.touch_from_children())),
+            ("dir-template", format!("{:?}", self.dir_template())),
+            ("lazy-dirs", format!("{:?}", self.lazy_dirs())),
+            ("dir-permissions", format!("{:?}", self.dir_permissions().map(|mode| format!("{mode:o}")))),
+            ("recurse", format!("{:?}", self.recurse())),
+            ("symlink-follow-depth", format!("{:?}", self.symlink_follow_depth())),
+            ("one-file-system", format!("{:?}", self.one_file_system())),
+            ("layout", format!("{:?}", self.layout())),
+            ("flatten-naming", format!("{:?}", self.flatten_naming())),
+            ("inode-cache", format!("{:?}", self.inode_cache())),
+            ("max-open-dirs", format!("{:?}", self.max_open_dirs())),
+            ("parallel-dirs", format!("{:?}", self.parallel_dirs())),
+            ("per-dir-limit", format!("{:?}", self.per_dir_limit())),
+            ("on-permission-denied", format!("{:?}", self.on_permission_denied())),
+            ("confirm-dir-name", format!("{:?}", self.confirm_dir_name())),
+            ("name-from-frontmatter", format!("{:?}", self.name_from_frontmatter())),
+            ("rewrite-ext", format!("{:?}", self.rewrite_ext())),
+            ("sanitize-names", format!("{:?}", self.sanitize_names())),
+            ("sanitize-replacement", format!("{:?}", self.sanitize_replacement())),
+            ("max-name-length", format!("{:?}", self.max_name_length())),
+            ("interactive-skip-key", format!("{:?}", self.interactive_skip_key())),
+            ("multiselect-per-dir", format!("{:?}", self.multiselect_per_dir())),
+            ("within-dir-order", format!("{:?}", self.within_dir_order())),
+            ("prompt-show-sizes", format!("{:?}", self.prompt_show_sizes())),
+            ("ask-to-rename-links", format!("{:?}", !self.never_prompt || self.ask_to_rename_links)),
+            ("rename-on-conflict-only", format!("{:?}", self.rename_on_conflict_only)),
+            ("refresh-stale", format!("{:?}", self.refresh_stale())),
+            ("merge", format!("{:?}", self.merge())),
+            ("on-conflict", format!("{:?}", self.on_conflict())),
+            ("hardlink-identical-only", format!("{:?}", self.hardlink_identical_only())),
+            ("protect-modified-target", format!("{:?}", self.protect_modified_target())),
+            ("backup-dir", format!("{:?}", self.backup_dir())),
+            ("write-provenance", format!("{:?}", self.write_provenance())),
+            ("provenance-format", format!("{:?}", self.provenance_format())),
+            ("provenance-dir", format!("{:?}", self.provenance_dir())),
+            ("confirm-overwrite", format!("{:?}", self.confirm_overwrite())),
+            ("adaptive-defaults", format!("{:?}", self.adaptive_defaults())),
+            ("auto-confirm-under", format!("{:?}", self.auto_confirm_under())),
+            ("noninteractive-fallback", format!("{:?}", self.noninteractive_fallback())),
+            ("assume-yes", format!("{:?}", self.assume_yes())),
+            ("on-orphan", format!("{:?}", self.on_orphan())),
+            ("remove-orphan-files", format!("{:?}", self.remove_orphan_files())),
+            ("name-map", format!("{:?}", self.name_map())),
+            ("remember-answers", format!("{:?}", self.remember_answers())),
+            ("forget", format!("{:?}", self.forget())),
+            ("batch-size", format!("{:?}", self.batch_size())),
+            ("checkpoint-every", format!("{:?}", self.checkpoint_every())),
+            ("timeout-total", format!("{:?}", self.timeout_total())),
+            ("index", format!("{:?}", self.index())),
+            ("log-file", format!("{:?}", self.log_file())),
+            ("resume", format!("{:?}", self.resume())),
+            ("skip-zero-byte-files", format!("{:?}", self.skip_zero_byte_files())),
+            ("multilink-threshold", format!("{:?}", self.multilink_threshold())),
+            ("min-age", format!("{:?}", self.min_age())),
+            ("max-age", format!("{:?}", self.max_age())),
+            ("require-stable", format!("{:?}", self.require_stable())),
+            ("filter-script", format!("{:?}", self.filter_script().map(ShellCommand::command))),
+            ("pre-hook", format!("{:?}", self.pre_hook().map(ShellCommand::command))),
+            ("post-hook", format!("{:?}", self.post_hook().map(ShellCommand::command))),
+            ("count-only", format!("{:?}", self.count_only())),
+            ("audit", format!("{:?}", self.audit())),
+            ("diff-against", format!("{:?}", self.diff_against())),
+            ("failures-file", format!("{:?}", self.failures_file())),
+            ("failures-nul", format!("{:?}", self.failures_nul())),
+            ("report-format", format!("{:?}", self.report_format())),
+            ("summary-by-ext", format!("{:?}", self.summary_by_ext())),
+            ("report-bytes", format!("{:?}", self.report_bytes())),
+            ("relative-output", format!("{:?}", self.relative_output)),
+            ("explain", format!("{:?}", self.explain())),
+            ("verbose", format!("{:?}", self.verbose())),
+        ];
+
+        settings.iter().fold(String::new(), |mut config, (name, value)| {
+            // Writing to a `String` can't fail.
+            let _ = writeln!(config, "{name}: {value}");
+            config
+        })
+    }
+
     pub fn completions(&self) -> Option<Shell> {
         self.completions
     }
@@ -144,4 +1426,44 @@ impl ShouldExit {
     pub const fn should_exit(&self) -> bool {
         matches!(self, Self::Yes)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn age_parses_every_unit() {
+        assert_eq!(age("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(age("5m"), Ok(Duration::from_mins(5)));
+        assert_eq!(age("2h"), Ok(Duration::from_hours(2)));
+        assert_eq!(age("1d"), Ok(Duration::from_hours(24)));
+        assert_eq!(age("2w"), Ok(Duration::from_hours(14 * 24)));
+        assert_eq!(age("1y"), Ok(Duration::from_hours(365 * 24)));
+        assert_eq!(age("0d"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn age_rejects_invalid_input() {
+        assert!(age("").is_err());
+        assert!(age("d").is_err());
+        assert!(age("30").is_err());
+        assert!(age("30x").is_err());
+        assert!(age("-3d").is_err());
+        assert!(age("1.5h").is_err());
+        assert!(age("3 d").is_err());
+    }
+
+    #[test]
+    fn age_rejects_overflow() {
+        assert_eq!(age("100000000000000000y"), Err("age is too large".to_string()));
+        assert!(age("100000000000000000000s").is_err());
+    }
+
+    #[test]
+    fn force_prompt_overrides_skip_prompt_if_linked() {
+        let parse = |args: &[&str]| Cli::try_parse_from(["tlu"].iter().chain(args).chain(&["base", "target"])).unwrap();
+        assert!(parse(&["--skip-prompt-if-linked"]).skip_prompt_if_linked());
+        assert!(!parse(&["--skip-prompt-if-linked", "--force"]).skip_prompt_if_linked());
+        assert!(!parse(&["--skip-prompt-if-linked", "--force-prompt"]).skip_prompt_if_linked());
+    }
+}
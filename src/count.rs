@@ -0,0 +1,209 @@
+//! The `--count-only` walk, which tallies what a run would do without doing any of it.
+
+use std::{
+    fs::{self, DirEntry, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::{Action, Cli, OnSymlinkPrivilegeError},
+    device::{self, Device},
+    link::{self, LinkStrategy},
+};
+
+#[derive(Debug, Default)]
+pub struct Counts {
+    pub links: u64,
+    pub dirs: u64,
+    /// Files copied instead of linked, assuming every symbolic link falls back to a copy if that's what
+    /// `--on-symlink-privilege-error` does.
+    pub copies: u64,
+    pub bytes_to_copy: u64,
+    /// Entries left out because they couldn't be read.
+    pub unreadable: u64,
+}
+
+/// Counts what a run over `bases` would do. The `bool` is `false` if `--filter-script` aborted the count early.
+pub fn count(bases: &[PathBuf], cli: &Cli) -> Result<(Counts, bool), String> {
+    let mut counts = Counts::default();
+    let target_device = if cli.auto_copy_cross_device() {
+        Some(device::device_of(cli.target_dir()).map_err(|err| format!("Failed to get <TARGET> device: {err}"))?)
+    } else {
+        None
+    };
+
+    for base in bases {
+        if base.is_file() {
+            counts.links += 1;
+            let relative = Path::new(base.file_name().unwrap_or_default());
+            fs::metadata(base)
+                .and_then(|metadata| count_copy(base, relative, &metadata, cli, target_device.as_ref(), &mut counts))
+                .map_err(|err| format!("Failed to count `{}`: {err}", base.display()))?;
+            continue;
+        }
+
+        let base_device = if cli.one_file_system() {
+            Some(device::device_of(base).map_err(|err| format!("Failed to get <BASE> device: {err}"))?)
+        } else {
+            None
+        };
+
+        let devices = (base_device.as_ref(), target_device.as_ref());
+        let complete = walk(base, base, cli, devices, 0, &mut counts)
+            .map_err(|err| format!("Failed to count `{}`: {err}", base.display()))?;
+        if !complete {
+            return Ok((counts, false));
+        }
+    }
+
+    Ok((counts, true))
+}
+
+/// Walks `dir` in `base` applying the same filters a run would, assuming every prompt is answered with yes.
+/// Entries that can't be read are reported and left out, like a run skips them.
+///
+/// Returns `false` if `--filter-script` aborted the walk. `devices` are those of `base` for `--one-file-system` and
+/// of <TARGET> for `--auto-copy-cross-device`. `symlinks` is how many symbolic links were followed to get to `dir`.
+fn walk(
+    base: &Path,
+    dir: &Path,
+    cli: &Cli,
+    devices: (Option<&Device>, Option<&Device>),
+    symlinks: usize,
+    counts: &mut Counts,
+) -> io::Result<bool> {
+    let mut files = 0;
+
+    for entry in dir.read_dir()? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                skip(dir, &err, counts);
+                continue;
+            }
+        };
+
+        match count_entry(base, &entry, cli, devices, symlinks, &mut files, counts) {
+            Ok(true) => {}
+            Ok(false) => return Ok(false),
+            Err(err) => skip(&entry.path(), &err, counts),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Counts a single entry of a directory, and everything below it. `files` is how many files of the directory were
+/// counted so far, for `--per-dir-limit`. Returns `false` if `--filter-script` aborted the walk.
+fn count_entry(
+    base: &Path,
+    entry: &DirEntry,
+    cli: &Cli,
+    devices: (Option<&Device>, Option<&Device>),
+    symlinks: usize,
+    files: &mut usize,
+    counts: &mut Counts,
+) -> io::Result<bool> {
+    let (base_device, target_device) = devices;
+    let path = entry.path();
+
+    if cli.is_root_marker(&path) {
+        return Ok(true);
+    }
+
+    let is_symlink = entry.file_type()?.is_symlink();
+    if is_symlink && cli.symlink_follow_depth().is_some_and(|max| symlinks >= max) {
+        return Ok(true);
+    }
+
+    let metadata = fs::metadata(&path)?;
+    if metadata.is_file() {
+        if cli.skip_zero_byte_files() && metadata.len() == 0 {
+            return Ok(true);
+        }
+        if let Some(threshold) = cli.multilink_threshold() {
+            if link::hard_link_count(&metadata).is_some_and(|count| count >= threshold) {
+                return Ok(true);
+            }
+        }
+        let by_age = cli.min_age().is_some() || cli.max_age().is_some();
+        if by_age && !cli.within_age(metadata.modified()?) {
+            return Ok(true);
+        }
+        if let Some(filter) = cli.filter_script() {
+            match filter.allows(&path)? {
+                Some(true) => {}
+                Some(false) => return Ok(true),
+                None => return Ok(false),
+            }
+        }
+
+        if cli.per_dir_limit().is_some_and(|limit| *files >= limit) {
+            return Ok(true);
+        }
+        *files += 1;
+        let relative = path.strip_prefix(base).unwrap_or(&path);
+        count_copy(&path, relative, &metadata, cli, target_device, counts)?;
+        counts.links += 1;
+        return Ok(true);
+    }
+
+    if let Some(base_device) = base_device {
+        if device::device_of(&path)? != *base_device {
+            return Ok(true);
+        }
+    }
+
+    if cli.dir_link_strategy().is_some() {
+        counts.links += 1;
+        return Ok(true);
+    }
+
+    // Only mirrored runs recreate directories, the other layouts put files into their own folders instead.
+    if cli.layout().mirrors() {
+        counts.dirs += 1;
+    }
+
+    let symlinks = symlinks + usize::from(is_symlink);
+    if cli.recurse() == Action::Never {
+        return Ok(true);
+    }
+    walk(base, &path, cli, devices, symlinks, counts)
+}
+
+/// Reports an entry that couldn't be counted.
+fn skip(path: &Path, err: &io::Error, counts: &mut Counts) {
+    eprintln!("Skipping `{}` while counting: {err}", path.display());
+    counts.unreadable += 1;
+}
+
+/// Adds the file at `path` to the copies if the run will copy it instead of linking it.
+fn count_copy(
+    path: &Path,
+    relative: &Path,
+    metadata: &Metadata,
+    cli: &Cli,
+    target_device: Option<&Device>,
+    counts: &mut Counts,
+) -> io::Result<()> {
+    let copies = match cli.file_strategy(relative) {
+        LinkStrategy::Copy => true,
+        LinkStrategy::Hard => match target_device {
+            Some(target_device) => device::device_of(path)? != *target_device,
+            None => false,
+        },
+        // Only Windows refuses symbolic links, see `is_symlink_privilege_error`.
+        LinkStrategy::SymbolicFile | LinkStrategy::SymbolicDir => {
+            cfg!(target_family = "windows") && cli.on_symlink_privilege_error() == OnSymlinkPrivilegeError::Copy
+        }
+        // Reflinks share the source's data.
+        LinkStrategy::Reflink => false,
+    };
+
+    if copies {
+        counts.copies += 1;
+        counts.bytes_to_copy += metadata.len();
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use std::{fs, io, path::Path};
+
+#[cfg(target_family = "unix")]
+pub type Device = u64;
+#[cfg(target_family = "windows")]
+pub type Device = std::ffi::OsString;
+
+/// Identifies the device (or on Windows the volume) `path` lives on. Follows symbolic links.
+#[cfg(target_family = "unix")]
+pub fn device_of(path: &Path) -> io::Result<Device> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fs::metadata(path)?.dev())
+}
+
+/// Identifies the device (or on Windows the volume) `path` lives on. Follows symbolic links.
+#[cfg(target_family = "windows")]
+pub fn device_of(path: &Path) -> io::Result<Device> {
+    use std::path::Component;
+
+    // The volume serial number is only available on nightly, compare volume roots instead.
+    match fs::canonicalize(path)?.components().next() {
+        Some(Component::Prefix(prefix)) => Ok(prefix.as_os_str().to_ascii_uppercase()),
+        _ => Ok(Device::new()),
+    }
+}
@@ -0,0 +1,35 @@
+use std::{
+    fs::{DirEntry, ReadDir},
+    io,
+    path::Path,
+    vec,
+};
+
+/// Entries of a directory, either read lazily from an open handle or read into memory up front.
+pub enum Entries {
+    Open(ReadDir),
+    Buffered(vec::IntoIter<io::Result<DirEntry>>),
+}
+
+impl Entries {
+    /// Opens `dir`. With `buffer` all entries are read right away and the directory handle is closed again.
+    pub fn read(dir: &Path, buffer: bool) -> io::Result<Self> {
+        let read_dir = dir.read_dir()?;
+        if buffer {
+            Ok(Self::Buffered(read_dir.collect::<Vec<_>>().into_iter()))
+        } else {
+            Ok(Self::Open(read_dir))
+        }
+    }
+}
+
+impl Iterator for Entries {
+    type Item = io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Open(read_dir) => read_dir.next(),
+            Self::Buffered(entries) => entries.next(),
+        }
+    }
+}
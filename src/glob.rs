@@ -0,0 +1,236 @@
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Checks whether `text` contains any of the glob metacharacters `*`, `?` or `[`.
+pub fn is_pattern(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+/// Matches a single name against a glob pattern.
+///
+/// Supports `*` (any run of characters), `?` (any single character) and `[...]` character classes
+/// (with `!` or `^` negation and `a-z` ranges). An unterminated `[` matches itself literally.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and the text position it is currently matching up to.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, len)) = match_class(&pattern[p..], text[t]) {
+                    if matched {
+                        p += len;
+                        t += 1;
+                        continue;
+                    }
+                } else if text[t] == '[' {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+            }
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let Some((star, star_t)) = backtrack else {
+            return false;
+        };
+        backtrack = Some((star, star_t + 1));
+        p = star + 1;
+        t = star_t + 1;
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the character class at the start of `class`.
+///
+/// Returns whether the class matched and how many pattern characters it spans, or `None` if the class is
+/// unterminated.
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(class.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &start = class.get(i)?;
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+
+        if class.get(i + 1) == Some(&'-') && class.get(i + 2).is_some_and(|&end| end != ']') {
+            let end = class[i + 2];
+            matched |= (start..=end).contains(&c);
+            i += 3;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+}
+
+/// Expands a glob pattern into the paths it matches, sorted.
+///
+/// Every component of `pattern` may contain glob metacharacters. Components without them are joined as is,
+/// so they don't have to be readable directories.
+pub fn expand(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut matched = vec![PathBuf::new()];
+
+    for component in pattern.components() {
+        let Component::Normal(name) = component else {
+            for path in &mut matched {
+                path.push(component);
+            }
+            continue;
+        };
+
+        let name = name.to_string_lossy();
+        if !is_pattern(&name) {
+            for path in &mut matched {
+                path.push(&*name);
+            }
+            matched.retain(|path| path.exists());
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for dir in &matched {
+            let read_from = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            let Ok(entries) = read_from.read_dir() else {
+                continue;
+            };
+
+            for entry in entries {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                // Like shells do, only match hidden entries when the pattern asks for them explicitly.
+                if file_name.starts_with('.') && !name.starts_with('.') {
+                    continue;
+                }
+                if matches(&name, &file_name) {
+                    next.push(dir.join(entry.file_name()));
+                }
+            }
+        }
+        matched = next;
+    }
+
+    matched.sort();
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(matches("*", ""));
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(matches("a*", "a"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(!matches("a*b", "aXXc"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("?", "a"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("?", ""));
+        assert!(!matches("a?c", "ac"));
+        assert!(!matches("a?c", "abbc"));
+    }
+
+    #[test]
+    fn class_ranges() {
+        assert!(matches("[a-z]", "m"));
+        assert!(matches("file[0-9].txt", "file7.txt"));
+        assert!(matches("[abc-]", "-"));
+        assert!(!matches("[a-z]", "M"));
+        assert!(!matches("[a-z]", "ab"));
+    }
+
+    #[test]
+    fn negated_classes() {
+        assert!(matches("[!x]", "y"));
+        assert!(matches("[^x]", "y"));
+        assert!(!matches("[!x]", "x"));
+        assert!(!matches("[!a-z]", "q"));
+        assert!(matches("[!a-z]", "Q"));
+    }
+
+    #[test]
+    fn escaping_with_classes() {
+        assert!(matches("[*]", "*"));
+        assert!(!matches("[*]", "a"));
+        assert!(matches("[?]", "?"));
+        assert!(matches("[[]", "["));
+        assert!(matches("[]]", "]"));
+        // An unterminated class is a literal `[`.
+        assert!(matches("a[b", "a[b"));
+        assert!(!matches("a[b", "ab"));
+    }
+
+    #[test]
+    fn is_pattern_detects_metacharacters() {
+        assert!(is_pattern("packages/*/dist"));
+        assert!(is_pattern("file?"));
+        assert!(is_pattern("[ab]"));
+        assert!(!is_pattern("packages/a/dist"));
+    }
+
+    #[test]
+    fn expand_matches_directories() {
+        let root = std::env::temp_dir().join(format!("tlu-glob-test-{}", process::id()));
+        for package in ["a", "b", ".hidden"] {
+            fs::create_dir_all(root.join("packages").join(package).join("dist")).unwrap();
+        }
+        fs::create_dir_all(root.join("packages/c")).unwrap();
+
+        let expanded = expand(&root.join("packages/*/dist")).unwrap();
+        let nothing = expand(&root.join("packages/*/missing")).unwrap();
+        let no_match = expand(&root.join("nope-*")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            expanded,
+            vec![root.join("packages/a/dist"), root.join("packages/b/dist")]
+        );
+        assert!(nothing.is_empty());
+        assert!(no_match.is_empty());
+    }
+}
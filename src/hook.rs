@@ -0,0 +1,52 @@
+use std::{ffi::OsStr, io, path::Path, process::Command};
+
+/// Exit code with which a `--filter-script` stops the whole run.
+const FILTER_ABORT: i32 = 255;
+
+/// A command split into words with shell quoting rules. It's executed directly, not through a shell.
+#[derive(Debug, Clone)]
+pub struct ShellCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl ShellCommand {
+    pub fn parse(command: &str) -> Result<Self, String> {
+        let mut words = shell_words::split(command).map_err(|err| format!("Can't split command: {err}"))?;
+        if words.is_empty() {
+            return Err("Command is empty".to_string());
+        }
+
+        let program = words.remove(0);
+        Ok(Self { program, args: words })
+    }
+
+    /// Runs the command to completion. The error describes why it failed.
+    pub fn run<K, V>(&self, envs: impl IntoIterator<Item = (K, V)>) -> Result<(), String>
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        match self.command().envs(envs).status() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("failed ({status})")),
+            Err(err) => Err(format!("couldn't be run: {err}")),
+        }
+    }
+
+    /// Runs the command as a `--filter-script` on `file`. `None` if it wants to abort the run.
+    pub fn allows(&self, file: &Path) -> io::Result<Option<bool>> {
+        let status = self.command().arg(file).status()?;
+        Ok(match status.code() {
+            Some(0) => Some(true),
+            Some(FILTER_ABORT) => None,
+            _ => Some(false),
+        })
+    }
+
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+}
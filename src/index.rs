@@ -0,0 +1,202 @@
+//! The `--index` sidecar remembering which sources were already linked.
+//!
+//! The file starts with a version header followed by one `source<TAB>dest<TAB>mtime` line per link, where `mtime`
+//! is the source's modification time in nanoseconds since the Unix epoch. `--resume` files use the same format but
+//! are appended to as soon as each link is created.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const HEADER: &str = "tlu-index 1";
+
+#[derive(Debug)]
+struct Entry {
+    dest: PathBuf,
+    mtime: u128,
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    entries: HashMap<PathBuf, Entry>,
+}
+
+/// Modification time of `path` in nanoseconds since the Unix epoch.
+pub fn mtime(path: &Path) -> io::Result<u128> {
+    let modified = fs::metadata(path)?.modified()?;
+    modified
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Paths with tabs or newlines would break the line format.
+fn storable(path: &Path) -> bool {
+    !path.to_string_lossy().contains(['\t', '\n', '\r'])
+}
+
+/// Checks whether the symbolic link `link` resolves to `original`, however its path is spelled.
+pub fn links_to(link: &Path, original: &Path) -> bool {
+    matches!(
+        (fs::canonicalize(link), fs::canonicalize(original)),
+        (Ok(link), Ok(original)) if link == original
+    )
+}
+
+/// Checks whether `a` and `b` resolve to the same file.
+#[cfg(target_family = "unix")]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Hard links can't be told apart from copies here, so they never verify and simply get redone.
+#[cfg(not(target_family = "unix"))]
+pub fn same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+impl Index {
+    /// Loads an index. A missing file is an empty index.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+
+        let mut lines = content.lines();
+        match lines.next() {
+            Some(HEADER) | None => {}
+            Some(header) => return Err(invalid_data(format!("Unsupported index header `{header}`"))),
+        }
+
+        let mut entries = HashMap::new();
+        for (number, line) in lines.enumerate() {
+            let mut fields = line.split('\t');
+            let (Some(source), Some(dest), Some(mtime), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid_data(format!("Malformed index line {}", number + 2)));
+            };
+            let mtime = mtime
+                .parse()
+                .map_err(|err| invalid_data(format!("Malformed index line {}: {err}", number + 2)))?;
+
+            entries.insert(
+                PathBuf::from(source),
+                Entry {
+                    dest: PathBuf::from(dest),
+                    mtime,
+                },
+            );
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Writes the index to `path`. It's written next to it first and renamed into place, so a crash while saving
+    /// leaves the previous index intact.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(source, entry)| format!("{}\t{}\t{}", source.display(), entry.dest.display(), entry.mtime))
+            .collect();
+        lines.sort();
+
+        let mut content = format!("{HEADER}\n");
+        for line in lines {
+            content.push_str(&line);
+            content.push('\n');
+        }
+
+        let mut temporary = path.file_name().unwrap_or_default().to_owned();
+        temporary.push(".tmp");
+        let temporary = path.with_file_name(temporary);
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Every recorded link as `(source, dest)`.
+    pub fn links(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.entries.iter().map(|(source, entry)| (source.as_path(), entry.dest.as_path()))
+    }
+
+    /// Checks whether `source` was linked while it had the modification time `mtime`.
+    pub fn is_current(&self, source: &Path, mtime: u128) -> bool {
+        self.entries.get(source).is_some_and(|entry| entry.mtime == mtime)
+    }
+
+    /// Checks whether the link recorded for `source` still exists and is a symbolic or hard link of it.
+    pub fn verifies(&self, source: &Path) -> bool {
+        self.entries
+            .get(source)
+            .is_some_and(|entry| links_to(&entry.dest, source) || same_file(&entry.dest, source))
+    }
+
+    /// The link recorded for `source` if it no longer verifies, so it has to be redone.
+    pub fn stale(&self, source: &Path) -> Option<&Path> {
+        match self.entries.get(source) {
+            Some(entry) if !self.verifies(source) => Some(&entry.dest),
+            _ => None,
+        }
+    }
+
+    /// Records that `source` was linked to `dest`. Paths with tabs or newlines can't be stored and are ignored.
+    pub fn insert(&mut self, source: &Path, dest: &Path, mtime: u128) {
+        if storable(source) && storable(dest) {
+            self.entries.insert(
+                source.to_path_buf(),
+                Entry {
+                    dest: dest.to_path_buf(),
+                    mtime,
+                },
+            );
+        }
+    }
+}
+
+/// A `--resume` file that every created link is written to right away.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Opens `path` for appending, writing the header if the file is new.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{HEADER}")?;
+        }
+
+        Ok(Self { file })
+    }
+
+    /// Appends that `source` was linked to `dest`. Paths with tabs or newlines can't be stored and are ignored.
+    pub fn record(&mut self, source: &Path, dest: &Path, mtime: u128) -> io::Result<()> {
+        if !storable(source) || !storable(dest) {
+            return Ok(());
+        }
+
+        writeln!(self.file, "{}\t{}\t{mtime}", source.display(), dest.display())
+    }
+
+    /// Makes sure everything recorded so far survives a crash of the whole system, not just of tlu.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
@@ -0,0 +1,114 @@
+//! The different ways files can be placed in <TARGET>.
+
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use clap::ValueEnum;
+
+use crate::{audit, names};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// Recreate <BASE>'s directories
+    Mirror,
+    /// Link files from every depth directly into <TARGET>
+    Flat,
+    /// Link files from every depth into a folder named after their extension, or `no-ext`
+    ByExt,
+    /// Link files from every depth into a `YYYY-MM` folder named after their modification month
+    ByDate,
+}
+
+impl Layout {
+    /// Whether this layout recreates <BASE>'s directories instead of computing a folder for every file.
+    pub const fn mirrors(self) -> bool {
+        matches!(self, Self::Mirror)
+    }
+
+    /// Folder of <TARGET> the file at `relative` in <BASE> goes into, relative to <TARGET>. Empty for <TARGET>
+    /// itself. Mirrored runs get here by walking, so this is only asked for the other layouts.
+    pub fn dest_dir(self, relative: &Path, metadata: &Metadata) -> PathBuf {
+        match self {
+            Self::Mirror => relative.parent().map(Path::to_path_buf).unwrap_or_default(),
+            Self::Flat => PathBuf::new(),
+            Self::ByExt => by_ext(relative),
+            Self::ByDate => by_date(metadata),
+        }
+    }
+}
+
+/// How files of different directories that end up with the same name in a `--layout` folder are told apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FlattenNaming {
+    /// Append `.1`, `.2` and so on
+    Number,
+    /// Prepend the name of the directory the file is in, like `music_track.mp3`
+    Parent,
+    /// Append a short hash of the file's path in <BASE> to its stem, like `track~1a2b3c4d.mp3`
+    Hash,
+}
+
+impl FlattenNaming {
+    /// Where the file at `relative` in <BASE> goes instead of `link` if `taken(link)`, which should be true for what
+    /// was placed earlier in the same run. Numbers what `parent` and `hash` can't tell apart either.
+    pub fn disambiguate(self, link: PathBuf, relative: &Path, taken: &dyn Fn(&Path) -> bool) -> PathBuf {
+        if !taken(&link) {
+            return link;
+        }
+
+        let name = link.file_name().unwrap_or_default();
+        let renamed = match self {
+            Self::Number => None,
+            Self::Parent => relative.parent().and_then(Path::file_name).map(|parent| {
+                let mut renamed = parent.to_owned();
+                renamed.push("_");
+                renamed.push(name);
+                renamed
+            }),
+            Self::Hash => {
+                let hash = format!("~{:08x}", names::fnv1a(&relative.to_string_lossy()));
+                let mut renamed = link.file_stem().unwrap_or_default().to_owned();
+                renamed.push(hash);
+                if let Some(extension) = link.extension() {
+                    renamed.push(".");
+                    renamed.push(extension);
+                }
+                Some(renamed)
+            }
+        };
+        let link = match renamed {
+            Some(renamed) => link.with_file_name(renamed),
+            None => link,
+        };
+
+        let base_name = link.file_name().unwrap_or_default().to_owned();
+        let mut numbered = link;
+        let mut number = 1;
+        while taken(&numbered) {
+            let mut name = base_name.clone();
+            name.push(format!(".{number}"));
+            numbered.set_file_name(name);
+            number += 1;
+        }
+
+        numbered
+    }
+}
+
+fn by_ext(relative: &Path) -> PathBuf {
+    relative
+        .extension()
+        .map_or_else(|| PathBuf::from("no-ext"), PathBuf::from)
+}
+
+fn by_date(metadata: &Metadata) -> PathBuf {
+    let Some(modified) = metadata.modified().ok().filter(|modified| *modified >= UNIX_EPOCH) else {
+        return PathBuf::from("no-date");
+    };
+
+    // `YYYY-MM-DDT...`, only the month is wanted.
+    PathBuf::from(&audit::timestamp(modified)[..7])
+}
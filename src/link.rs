@@ -0,0 +1,122 @@
+//! The different ways a link can be created.
+
+use std::{fmt, fs, io, path::Path};
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LinkStrategy {
+    /// Hard link
+    Hard,
+    /// Symbolic link to a file
+    SymbolicFile,
+    /// Symbolic link to a directory. Same as symbolic-file outside Windows
+    SymbolicDir,
+    /// Copy-on-write clone sharing the source's data. Linux only, needs a filesystem like Btrfs or XFS
+    Reflink,
+    /// Plain copy
+    Copy,
+}
+
+impl LinkStrategy {
+    /// Name of the strategy as given to `--link-strategy`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Hard => "hard",
+            Self::SymbolicFile => "symbolic-file",
+            Self::SymbolicDir => "symbolic-dir",
+            Self::Reflink => "reflink",
+            Self::Copy => "copy",
+        }
+    }
+
+    pub const fn is_symbolic(self) -> bool {
+        matches!(self, Self::SymbolicFile | Self::SymbolicDir)
+    }
+
+    /// The strategy to actually link `original` with. Windows needs a different kind of symbolic link for
+    /// directories, so `symbolic-file` turns into `symbolic-dir` when `original` is one.
+    pub fn for_source(self, original: &Path) -> Self {
+        if self == Self::SymbolicFile && original.is_dir() {
+            Self::SymbolicDir
+        } else {
+            self
+        }
+    }
+
+    /// Creates `link` from `original` with this strategy.
+    pub fn link(self, original: &Path, link: &Path) -> io::Result<()> {
+        match self {
+            Self::Hard => fs::hard_link(original, link).map_err(|err| {
+                if err.kind() == io::ErrorKind::CrossesDevices {
+                    io::Error::new(err.kind(), format!("{err}, hard links can't cross filesystems"))
+                } else {
+                    err
+                }
+            }),
+            #[cfg(target_family = "unix")]
+            Self::SymbolicFile | Self::SymbolicDir => std::os::unix::fs::symlink(original, link),
+            #[cfg(target_family = "windows")]
+            Self::SymbolicFile => std::os::windows::fs::symlink_file(original, link),
+            #[cfg(target_family = "windows")]
+            Self::SymbolicDir => std::os::windows::fs::symlink_dir(original, link),
+            Self::Reflink => reflink(original, link),
+            Self::Copy => fs::copy(original, link).map(|_| ()),
+        }
+    }
+}
+
+/// Creates the links of a run. Runs go through this instead of calling [`LinkStrategy::link`] themselves, so tests can
+/// substitute a fake that records what would have been linked.
+pub trait Linker: fmt::Debug + Send + Sync {
+    fn link(&self, strategy: LinkStrategy, original: &Path, link: &Path) -> io::Result<()>;
+}
+
+/// Links on the actual filesystem.
+#[derive(Debug, Default)]
+pub struct Filesystem;
+
+impl Linker for Filesystem {
+    fn link(&self, strategy: LinkStrategy, original: &Path, link: &Path) -> io::Result<()> {
+        strategy.link(original, link)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(original: &Path, link: &Path) -> io::Result<()> {
+    use std::{fs::File, os::fd::AsRawFd};
+
+    let source = File::open(original)?;
+    let dest = File::options().write(true).create_new(true).open(link)?;
+    // SAFETY: Both descriptors stay open for the duration of the call.
+    if unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == -1 {
+        let err = io::Error::last_os_error();
+        drop(dest);
+        // Don't leave an empty file behind where the link should be.
+        let _ = fs::remove_file(link);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reflinks are only supported on Linux"))
+}
+
+/// How many hard links point at the file `metadata` belongs to. `None` where that isn't available.
+#[cfg(target_family = "unix")]
+#[allow(clippy::unnecessary_wraps)] // Only `None` on other platforms.
+pub fn hard_link_count(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.nlink())
+}
+
+/// How many hard links point at the file `metadata` belongs to. `None` where that isn't available.
+#[cfg(not(target_family = "unix"))]
+pub fn hard_link_count(_metadata: &fs::Metadata) -> Option<u64> {
+    // The link count is only exposed on nightly outside Unix.
+    None
+}
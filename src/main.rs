@@ -1,63 +1,149 @@
 #![warn(clippy::pedantic)]
 
+/// `println!` through [`output`], so `--parallel-dirs` can print every subtree in walk order.
+macro_rules! println {
+    () => {
+        $crate::output::print($crate::output::Stream::Stdout, String::from("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::print($crate::output::Stream::Stdout, format!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// `eprintln!` through [`output`], like `println!`.
+macro_rules! eprintln {
+    () => {
+        $crate::output::print($crate::output::Stream::Stderr, String::from("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::print($crate::output::Stream::Stderr, format!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+mod answers;
+mod atomic;
+mod audit;
 mod cli;
+mod count;
+mod device;
+mod entries;
+mod glob;
+mod hook;
+mod index;
+mod layout;
+mod link;
+mod mirror;
+mod names;
+mod orphans;
+mod output;
+mod plan;
+mod preflight;
+mod provenance;
+mod run;
+mod size;
+mod summary;
+mod xattr;
 
 use std::{
-    fs::{self, DirEntry, FileType, ReadDir},
-    io,
-    path::{Path, PathBuf},
+    collections::VecDeque,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::{self, DirEntry, File, FileType},
+    io::{self, IsTerminal, Read},
+    iter, mem, panic,
+    path::{Component, Path, PathBuf},
     process::ExitCode,
+    sync::{atomic::AtomicUsize, Arc},
+    thread::{self, Scope, ScopedJoinHandle},
+    time::{Duration, Instant, SystemTime},
 };
 
+use audit::{AuditLog, Level};
 use clap::{CommandFactory, Parser};
 use clap_complete::generate;
-use cli::{Cli, ShouldExit, Action};
-use dialoguer::{Confirm, Error, Input};
+use cli::{
+    Action, Cli, NoninteractiveFallback, OnConflict, OnOrphan, OnPermissionDenied, OnSymlinkPrivilegeError, ShouldExit,
+    SymlinkPolicy, WithinDirOrder,
+};
+use dialoguer::{Confirm, Error, Input, MultiSelect, Select};
+use entries::Entries;
+use index::{links_to, Index, Journal};
+use link::LinkStrategy;
+use output::Lines;
+use run::{Displaced, Prompt, Run};
 
 /// Prompts the user to create a link and creates one if they agree.
 ///
 /// `original` File to create a link to.
 /// `link` Link that will point to `original`
+/// `strategy` How to create the link
 ///
 /// # Panics
 ///
 /// When link doesn't contain a filename.
 ///
-fn link_file(original: &Path, link: &Path, cli: &Cli) -> io::Result<ShouldExit> {
-    let maybe_link_name = link.file_name();
-    assert!(
-        maybe_link_name.is_some(),
-        "`link` didn't contain a file name. `link`: {}",
-        link.display()
-    );
-    let link_file_name = maybe_link_name.unwrap();
+fn link_file(
+    original: &Path,
+    link: &Path,
+    strategy: LinkStrategy,
+    cli: &Cli,
+    run: &mut Run,
+) -> io::Result<ShouldExit> {
+    let mut named = link.with_file_name(link_name(original, link, cli, run, true));
+    if !cli.layout().mirrors() {
+        // Files from different directories can get the same name in a --layout folder.
+        let relative = run.relative_source(original);
+        named = cli.flatten_naming().disambiguate(named, relative, &|named| run.flattened.contains(named));
+        run.flattened.insert(named.clone());
+    }
+    let link_file_name = named.file_name().unwrap_or_default().to_owned();
 
-    let create_link = if cli.create_links() == Action::Always {
-        Some(true)
-    } else {
-        Confirm::new()
-            .with_prompt(format!(
-                "Create link from `{}` to `{}`?",
-                link.display(),
-                original.display()
-            ))
-            .default(true)
-            .interact_opt()
-            .map_err(|Error::IO(err)| err)?
+    let mtime = match &run.index {
+        Some(index) => {
+            let mtime = index::mtime(original)?;
+            // The staging directory of --atomic-run starts out empty, so nothing can be skipped.
+            if run.staging.is_none() && index.is_current(original, mtime) {
+                run.summary.skipped += 1;
+                run.log(Level::Info, "link", Some(original), Some(&named), "skipped, up to date in --index");
+                explain(cli, original, format_args!("skipped, unchanged since --index recorded its link"));
+                return Ok(ShouldExit::No);
+            }
+
+            Some(mtime)
+        }
+        None => None,
     };
 
+    if run.staging.is_none() && run.resumed.as_ref().is_some_and(|resumed| resumed.verifies(original)) {
+        run.summary.skipped += 1;
+        run.log(Level::Info, "link", Some(original), Some(&named), "skipped, already linked by the resumed run");
+        explain(cli, original, format_args!("skipped, already linked by the --resume run"));
+        return Ok(ShouldExit::No);
+    }
+
+    if cli.skip_prompt_if_linked() && is_linked_already(original, &named, cli, run) {
+        return Ok(ShouldExit::No);
+    }
+
+    let create_link = ask_create_link(original, &named, cli, run)?;
+
     let Some(create_link) = create_link else {
         return Ok(ShouldExit::Yes);
     };
 
     if !create_link {
+        run.summary.skipped += 1;
+        run.log(Level::Info, "link", Some(original), Some(&named), "declined");
+        explain(cli, original, format_args!("skipped, declined at the prompt"));
         return Ok(ShouldExit::No);
     }
 
-    let link_file_name: String = if cli.ask_to_rename_links() {
+    let exists = named.symlink_metadata().is_ok();
+    let link_file_name: String = if cli.ask_to_rename_links(exists) {
         Input::new()
-            .with_prompt("Link name")
+            .with_prompt(if exists { "Link name (exists)" } else { "Link name" })
             .with_initial_text(link_file_name.to_string_lossy())
+            .validate_with(|name: &String| names::validate(name))
             .interact_text() // For some reason supports utf-8
             .map_err(|Error::IO(err)| err)?
     } else {
@@ -66,31 +152,616 @@ fn link_file(original: &Path, link: &Path, cli: &Cli) -> io::Result<ShouldExit>
 
     let mut link = link.to_path_buf();
     link.set_file_name(link_file_name);
-    let link_function = cli.link_function();
-    link_function(original, link)?;
+
+    clear_stale(original, &link, cli, run)?;
+    if link.symlink_metadata().is_ok() {
+        if let Some(should_exit) = make_room(original, &link, cli, run)? {
+            run.log(Level::Info, "link", Some(original), Some(&link), "skipped, link exists");
+            return Ok(should_exit);
+        }
+    }
+
+    // Hardlinking a missing source fails anyway, this only really guards symbolic links.
+    if cli.require_target_exists() {
+        if let Err(err) = run.source_metadata(cli, original) {
+            return Err(io::Error::new(
+                err.kind(),
+                format!("Source `{}` isn't accessible: {err}", cli.show(original)),
+            ));
+        }
+    }
+
+    // Only copies asked for with --link-strategy or --copy-matching are moved, not the --auto-copy-cross-device ones.
+    let moves = cli.move_source() && strategy == LinkStrategy::Copy;
+    let strategy = cross_device_strategy(original, strategy, cli, run)?;
+    let points_to = if strategy.is_symbolic() {
+        retarget(symlink_source(original, &link, cli)?, cli)?
+    } else {
+        original.to_path_buf()
+    };
+
+    create_pending_dirs(cli, run)?;
+    if let Err(err) = run.link(strategy.for_source(original), &points_to, &link) {
+        if !is_symlink_privilege_error(&err) {
+            return Err(err);
+        }
+        if symlink_privilege_fallback(original, &link, &err, cli, run)?.should_exit() {
+            return Ok(ShouldExit::Yes);
+        }
+    } else if strategy == LinkStrategy::Copy {
+        preserve_metadata(original, &link, cli);
+    }
+    record_link(original, &link, strategy, mtime, cli, run)?;
+
+    if moves {
+        remove_source(original, &link, cli, run)?;
+    }
 
     Ok(ShouldExit::No)
 }
 
+/// Counts, logs and records the freshly created `link` to `original` everywhere the run keeps track of links.
+/// `mtime` is the source's modification time for the `--index`.
+fn record_link(
+    original: &Path,
+    link: &Path,
+    strategy: LinkStrategy,
+    mtime: Option<u128>,
+    cli: &Cli,
+    run: &mut Run,
+) -> io::Result<()> {
+    run.summary.links_created += 1;
+    // A hard link changes the link count, a copy may have been told to remove the source.
+    run.forget_metadata(original);
+    run.created(cli, link);
+    if let Some(dir) = link.parent().filter(|_| cli.touch_target_dirs()) {
+        run.written_dirs.insert(dir.to_path_buf());
+    }
+    if cli.summary_by_ext() || cli.report_bytes() {
+        count_bytes(original, cli, run)?;
+    }
+    if cli.write_provenance() {
+        write_provenance(original, link, cli, run);
+    }
+    explain(cli, original, format_args!("linked with the {} strategy", strategy.name()));
+    run.log(Level::Info, "link", Some(original), Some(link), "created");
+
+    let dest = run.final_path(link);
+    if let (Some(index), Some(mtime)) = (&mut run.index, mtime) {
+        index.insert(original, &dest, mtime);
+    }
+
+    if let Some(journal) = &mut run.journal {
+        journal
+            .record(original, &dest, index::mtime(original)?)
+            .map_err(|err| io::Error::new(err.kind(), format!("Failed to record link in --resume file: {err}")))?;
+    }
+
+    Ok(())
+}
+
+/// Removes `link` if it's what the run being continued with `--resume` created for `original` but it no longer links to
+/// it, so it's redone instead of running into `--on-conflict`.
+fn clear_stale(original: &Path, link: &Path, cli: &Cli, run: &Run) -> io::Result<()> {
+    let Some(resumed) = &run.resumed else {
+        return Ok(());
+    };
+    let is_file = link.symlink_metadata().is_ok_and(|metadata| !metadata.is_dir());
+    if resumed.stale(original) != Some(link) || !is_file {
+        return Ok(());
+    }
+
+    explain(cli, original, format_args!("redoing `{}`, the --resume run's link doesn't verify", cli.show(link)));
+    fs::remove_file(link)
+}
+
+/// Copies `original` instead of hard linking it when `--auto-copy-cross-device` finds it on another device than
+/// <TARGET>.
+fn cross_device_strategy(original: &Path, strategy: LinkStrategy, cli: &Cli, run: &Run) -> io::Result<LinkStrategy> {
+    match &run.target_device {
+        Some(target_device) if strategy == LinkStrategy::Hard && device::device_of(original)? != *target_device => {
+            explain(cli, original, format_args!("copying, it's on another device and --auto-copy-cross-device is set"));
+            Ok(LinkStrategy::Copy)
+        }
+        _ => Ok(strategy),
+    }
+}
+
+/// Gives `copy` what `--preserve-xattr` and `--preserve-permissions` ask to keep of `original`.
+fn preserve_metadata(original: &Path, copy: &Path, cli: &Cli) {
+    if cli.preserve_xattr() {
+        preserve_xattr(original, copy, cli);
+    }
+    if cli.preserve_permissions() {
+        preserve_permissions(original, copy, cli);
+    }
+}
+
+/// Gives `copy` the permissions of `original` for `--preserve-permissions`, only warning if that fails.
+fn preserve_permissions(original: &Path, copy: &Path, cli: &Cli) {
+    if let Err(err) = fs::metadata(original).and_then(|metadata| fs::set_permissions(copy, metadata.permissions())) {
+        eprintln!("Failed to copy the permissions of `{}`: {err}", cli.show(original));
+    } else {
+        explain(cli, original, format_args!("copied its permissions, --preserve-permissions is set"));
+    }
+}
+
+/// Gives `copy` the extended attributes of `original` for `--preserve-xattr`, only warning if that fails.
+fn preserve_xattr(original: &Path, copy: &Path, cli: &Cli) {
+    if let Err(err) = xattr::copy(original, copy) {
+        eprintln!("Failed to copy the extended attributes of `{}`: {err}", cli.show(original));
+    } else {
+        explain(cli, original, format_args!("copied its extended attributes, --preserve-xattr is set"));
+    }
+}
+
+/// Deletes `original` for `--move-source` after checking that `copy` has the same content.
+fn remove_source(original: &Path, copy: &Path, cli: &Cli, run: &mut Run) -> io::Result<()> {
+    if !has_same_content(original, copy)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The copy `{}` differs from `{}`, not deleting the source",
+                cli.show(copy),
+                cli.show(original)
+            ),
+        ));
+    }
+
+    fs::remove_file(original)?;
+    explain(cli, original, format_args!("deleted after verifying its copy, --move-source is set"));
+    run.log(Level::Info, "move", Some(original), Some(copy), "source deleted");
+    Ok(())
+}
+
+/// Writes the `--write-provenance` sidecar of `link`, only warning if that fails.
+fn write_provenance(original: &Path, link: &Path, cli: &Cli, run: &mut Run) {
+    let sidecar = provenance::sidecar_path(link, cli.target_dir(), cli.provenance_dir());
+    // Sidecars of earlier runs aren't this run's to remove with --confirm-at-end.
+    let existed = sidecar.symlink_metadata().is_ok();
+    let written = canonical_parent(original)
+        .and_then(|source| provenance::write(&sidecar, &source, &run.started, cli.provenance_format()));
+
+    match written {
+        Ok(()) if !existed => run.created(cli, &sidecar),
+        Ok(()) => {}
+        Err(err) => eprintln!("Failed to write provenance sidecar `{}`: {err}", cli.show(&sidecar)),
+    }
+}
+
+/// Adds a link to `original` to the `--summary-by-ext` breakdown and the `--report-bytes` total. Linked directories
+/// aren't counted.
+fn count_bytes(original: &Path, cli: &Cli, run: &mut Run) -> io::Result<()> {
+    let metadata = run.source_metadata(cli, original)?;
+    if !metadata.is_file() {
+        return Ok(());
+    }
+
+    if cli.summary_by_ext() {
+        let extension = original.extension().unwrap_or_default().to_string_lossy();
+        run.summary.count_extension(&extension, metadata.len());
+    }
+    if let Some(bytes) = &mut run.summary.bytes_linked {
+        *bytes += metadata.len();
+    }
+    Ok(())
+}
+
+/// Checks for `--skip-prompt-if-linked` whether `link` already links to `original`, skipping it if so. Links that
+/// wouldn't be prompted for are left to `--on-conflict`.
+fn is_linked_already(original: &Path, link: &Path, cli: &Cli, run: &mut Run) -> bool {
+    if cli.create_links() == Action::Always || !(links_to(link, original) || index::same_file(link, original)) {
+        return false;
+    }
+
+    run.summary.skipped += 1;
+    run.log(Level::Info, "link", Some(original), Some(link), "skipped, already linked");
+    explain(cli, original, format_args!("skipped without asking, `{}` already links to it", cli.show(link)));
+    true
+}
+
+/// Decides whether to link `original`, asking if needed. `None` if the user wants to exit.
+fn ask_create_link(original: &Path, link: &Path, cli: &Cli, run: &mut Run) -> io::Result<Option<bool>> {
+    if cli.create_links() == Action::Always {
+        explain(cli, original, format_args!("linking without asking, -f or --always-create-links is set"));
+        return Ok(Some(true));
+    }
+    if let Some(selected) = run.selection.remove(original) {
+        if selected {
+            explain(cli, original, format_args!("linking, picked in the --multiselect-per-dir list"));
+        }
+        return Ok(Some(selected));
+    }
+
+    let size = match cli.prompt_show_sizes().then(|| run.source_metadata(cli, original)) {
+        Some(Ok(metadata)) if metadata.is_file() => format!(" ({})", size::format(metadata.len())),
+        _ => String::new(),
+    };
+    let prompt = format!("Create link from `{}` to `{}`{size}?", cli.show(link), cli.show(original));
+    if let Some(&answer) = run.remembered.get(original) {
+        eprintln!("{prompt} {} (remembered)", if answer { "yes" } else { "no" });
+        return Ok(Some(answer));
+    }
+    let default = run.confirm_default(cli, Prompt::CreateLink);
+    let answer = if cli.interactive_skip_key() {
+        let choice = Select::new()
+            .with_prompt(prompt)
+            .items(&["Yes", "No", "Skip the rest of this directory"])
+            .default(usize::from(!default))
+            .interact_opt()
+            .map_err(|Error::IO(err)| err)?;
+        if choice == Some(2) {
+            explain(cli, original, format_args!("skipped with the rest of its directory at the prompt"));
+            run.skip_rest_of_dir = true;
+            return Ok(Some(false));
+        }
+        choice.map(|choice| choice == 0)
+    } else {
+        Confirm::new()
+            .with_prompt(prompt)
+            .default(default)
+            .interact_opt()
+            .map_err(|Error::IO(err)| err)?
+    };
+    run.remember_answer(Prompt::CreateLink, answer);
+    if let (Some(answer), Some(_)) = (answer, cli.remember_answers()) {
+        run.remembered.insert(original.to_path_buf(), answer);
+    }
+
+    if answer == Some(true) {
+        explain(cli, original, format_args!("linking, confirmed at the prompt"));
+    }
+
+    Ok(answer)
+}
+
+/// Prints why `path` is handled the way it is for `--explain`.
+fn explain(cli: &Cli, path: &Path, why: fmt::Arguments) {
+    if cli.explain() {
+        eprintln!("`{}`: {why}", cli.show(path));
+    }
+}
+
+/// `ERROR_PRIVILEGE_NOT_HELD`, what Windows fails symbolic link creation with unless Developer Mode is on or we're
+/// elevated.
+const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+fn is_symlink_privilege_error(err: &io::Error) -> bool {
+    cfg!(target_family = "windows") && err.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD)
+}
+
+/// Links `original` some other way after Windows refused to create a symbolic link, according to
+/// `--on-symlink-privilege-error`.
+fn symlink_privilege_fallback(
+    original: &Path,
+    link: &Path,
+    err: &io::Error,
+    cli: &Cli,
+    run: &mut Run,
+) -> io::Result<ShouldExit> {
+    if !run.privilege_explained {
+        eprintln!(
+            "Windows doesn't allow creating symbolic links without privileges: {err}. Enable Developer Mode in the \
+             Windows settings or run tlu as administrator to create them"
+        );
+        run.privilege_explained = true;
+    }
+
+    match cli.on_symlink_privilege_error() {
+        OnSymlinkPrivilegeError::Abort => {
+            eprintln!("Couldn't link `{}`, stopping", cli.show(original));
+            run.fail(original);
+            run.failed_fatally = true;
+            Ok(ShouldExit::Yes)
+        }
+        OnSymlinkPrivilegeError::Hardlink => run.link(LinkStrategy::Hard, original, link).map(|()| ShouldExit::No),
+        OnSymlinkPrivilegeError::Copy => {
+            run.link(LinkStrategy::Copy, original, link)?;
+            preserve_metadata(original, link, cli);
+            Ok(ShouldExit::No)
+        }
+    }
+}
+
+/// Computes the name of the link to `original`, applying `--name-map` or `--name-from-frontmatter`, `--rewrite-ext`
+/// and `--max-name-length` in that order. With `announce` renames are reported at `-v`.
+fn link_name(original: &Path, link: &Path, cli: &Cli, run: &Run, announce: bool) -> OsString {
+    let maybe_link_name = link.file_name();
+    assert!(
+        maybe_link_name.is_some(),
+        "`link` didn't contain a file name. `link`: {}",
+        link.display()
+    );
+    let link_file_name = match run.mapped_name(original) {
+        Some(name) => OsString::from(name),
+        None => frontmatter_name(original, cli).unwrap_or_else(|| maybe_link_name.unwrap().to_owned()),
+    };
+    let link_file_name = names::rewrite_extension(&link_file_name, cli.rewrite_ext()).unwrap_or(link_file_name);
+    fit_name(sanitize_name(link_file_name, cli, run, announce), cli, announce)
+}
+
+/// Name `--name-from-frontmatter` gives to links pointing at `original`, if it has the key.
+fn frontmatter_name(original: &Path, cli: &Cli) -> Option<OsString> {
+    let key = cli.name_from_frontmatter()?;
+    // Directories and unreadable files simply keep their name, linking reports real problems.
+    let slug = names::slugify(&names::frontmatter_value(original, key).ok()??);
+    if slug.is_empty() {
+        return None;
+    }
+
+    let mut name = OsString::from(slug);
+    if let Some(extension) = original.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    Some(name)
+}
+
+/// Applies `--sanitize-names` to a computed link name.
+fn sanitize_name(name: OsString, cli: &Cli, run: &Run, announce: bool) -> OsString {
+    if !cli.sanitize_names() {
+        return name;
+    }
+
+    let lossy = name.to_string_lossy();
+    let sanitized = names::sanitize(&lossy, run.restricted_names, cli.sanitize_replacement());
+    if sanitized == lossy {
+        return name;
+    }
+    if announce && cli.verbose() >= 1 {
+        eprintln!("Sanitizing `{lossy}` to `{sanitized}`");
+    }
+    OsString::from(sanitized)
+}
+
+/// Applies `--max-name-length` to a computed link name.
+fn fit_name(name: OsString, cli: &Cli, announce: bool) -> OsString {
+    match cli.max_name_length() {
+        Some(max) if name.len() > max => {
+            let shortened = names::truncate(&name.to_string_lossy(), max);
+            if announce && cli.verbose() >= 1 {
+                eprintln!("Shortening `{}` to `{shortened}`", name.to_string_lossy());
+            }
+            OsString::from(shortened)
+        }
+        _ => name,
+    }
+}
+
+/// Deals with `link` already existing according to `--refresh-stale` and `--on-conflict`.
+///
+/// Returns `Some` if the link shouldn't be created after all.
+fn make_room(original: &Path, link: &Path, cli: &Cli, run: &mut Run) -> io::Result<Option<ShouldExit>> {
+    if cli.merge() && (links_to(link, original) || index::same_file(link, original)) {
+        explain(cli, original, format_args!("skipped, `{}` already links to it and --merge is set", cli.show(link)));
+        run.summary.skipped += 1;
+        return Ok(Some(ShouldExit::No));
+    }
+
+    if cli.refresh_stale() && link.symlink_metadata()?.file_type().is_symlink() {
+        if links_to(link, original) {
+            explain(cli, original, format_args!("skipped, `{}` already links to it", cli.show(link)));
+            run.summary.skipped += 1;
+            return Ok(Some(ShouldExit::No));
+        }
+
+        if cli.verbose() >= 1 {
+            eprintln!("Replacing stale link `{}`", cli.show(link));
+        }
+        displace(link, cli, run)?;
+        return Ok(None);
+    }
+
+    match cli.on_conflict() {
+        // Let the link function report the existing destination.
+        OnConflict::Error => {}
+        OnConflict::Skip => {
+            explain(cli, original, format_args!("skipped, `{}` exists and --on-conflict is skip", cli.show(link)));
+            run.summary.skipped += 1;
+            return Ok(Some(ShouldExit::No));
+        }
+        OnConflict::Overwrite | OnConflict::Newer => {
+            if cli.on_conflict() == OnConflict::Newer && !is_newer(original, link)? {
+                eprintln!(
+                    "Not overwriting `{}`, `{}` isn't newer",
+                    cli.show(link),
+                    cli.show(original)
+                );
+                run.summary.skipped += 1;
+                return Ok(Some(ShouldExit::No));
+            }
+
+            if cli.protect_modified_target() && is_edited(original, link)? {
+                eprintln!(
+                    "Not overwriting `{}`, it was modified after `{}` and may hold local edits",
+                    cli.show(link),
+                    cli.show(original)
+                );
+                run.summary.skipped += 1;
+                return Ok(Some(ShouldExit::No));
+            }
+
+            if cli.hardlink_identical_only() && !has_same_content(original, link)? {
+                eprintln!(
+                    "Not overwriting `{}`, its content differs from `{}`",
+                    cli.show(link),
+                    cli.show(original)
+                );
+                run.summary.skipped += 1;
+                return Ok(Some(ShouldExit::No));
+            }
+
+            if cli.confirm_overwrite() {
+                let overwrite = Confirm::new()
+                    .with_prompt(format!(
+                        "`{}` already exists. Replace it with a link to `{}`?",
+                        cli.show(link),
+                        cli.show(original)
+                    ))
+                    .default(false)
+                    .interact_opt()
+                    .map_err(|Error::IO(err)| err)?;
+
+                match overwrite {
+                    None => return Ok(Some(ShouldExit::Yes)),
+                    Some(false) => {
+                        explain(cli, original, format_args!("skipped, declined replacing `{}`", cli.show(link)));
+                        run.summary.skipped += 1;
+                        return Ok(Some(ShouldExit::No));
+                    }
+                    Some(true) => {}
+                }
+            }
+
+            explain(cli, original, format_args!("replacing `{}` because of --on-conflict", cli.show(link)));
+
+            displace(link, cli, run)?;
+        }
+        OnConflict::Backup => {
+            let backup = backup_path(link, cli);
+            explain(cli, original, format_args!("moving `{}` to `{}` first", cli.show(link), cli.show(&backup)));
+            if let Some(parent) = backup.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(link, &backup)?;
+            if cli.confirm_at_end() {
+                run.displaced.push(Displaced {
+                    moved_to: backup,
+                    dest: link.to_path_buf(),
+                    discard: false,
+                });
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Deletes the existing destination `link` to make room for the new link. With `--confirm-at-end` it's only moved
+/// aside, so answering no can put it back.
+fn displace(link: &Path, cli: &Cli, run: &mut Run) -> io::Result<()> {
+    if !cli.confirm_at_end() {
+        // Windows removes symbolic links to directories like directories.
+        return fs::remove_file(link).or_else(|_| fs::remove_dir(link));
+    }
+
+    let mut name = link.file_name().unwrap_or_default().to_owned();
+    name.push(".tlu-displaced");
+    let moved_to = unused_path(link.with_file_name(name));
+    fs::rename(link, &moved_to)?;
+    run.displaced.push(Displaced {
+        moved_to,
+        dest: link.to_path_buf(),
+        discard: true,
+    });
+    Ok(())
+}
+
+/// Checks whether `original` was modified after the existing destination `link`.
+fn is_newer(original: &Path, link: &Path) -> io::Result<bool> {
+    // A dangling symbolic link has nothing to compare against but itself.
+    let existing = fs::metadata(link).or_else(|_| link.symlink_metadata())?;
+    Ok(fs::metadata(original)?.modified()? > existing.modified()?)
+}
+
+/// Checks for `--protect-modified-target` whether the existing destination `link` is a file of its own, not a link to
+/// `original`, that was modified after it.
+fn is_edited(original: &Path, link: &Path) -> io::Result<bool> {
+    let existing = link.symlink_metadata()?;
+    Ok(existing.is_file() && !index::same_file(link, original) && fs::metadata(original)?.modified()? < existing.modified()?)
+}
+
+/// Picks where to move an existing `link` for `--on-conflict backup`. Never returns an existing path.
+fn backup_path(link: &Path, cli: &Cli) -> PathBuf {
+    let backup = if let Some(backup_dir) = cli.backup_dir() {
+        let relative = link
+            .strip_prefix(cli.target_dir())
+            .unwrap_or_else(|_| Path::new(link.file_name().unwrap_or_default()));
+        backup_dir.join(relative)
+    } else {
+        let mut name = link.file_name().unwrap_or_default().to_owned();
+        name.push(".bak");
+        link.with_file_name(name)
+    };
+
+    unused_path(backup)
+}
+
+/// Numbers `path` with `.1`, `.2` and so on until nothing exists there.
+fn unused_path(path: PathBuf) -> PathBuf {
+    let mut numbered = path;
+    let mut number = 1;
+    let base_name = numbered.file_name().unwrap_or_default().to_owned();
+    while numbered.symlink_metadata().is_ok() {
+        let mut name = base_name.clone();
+        name.push(format!(".{number}"));
+        numbered.set_file_name(name);
+        number += 1;
+    }
+
+    numbered
+}
+
+/// Compares the contents of two files byte by byte.
+fn has_same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+
+    let mut a = File::open(a)?;
+    let mut b = File::open(b)?;
+    let mut a_buf = [0; 8192];
+    let mut b_buf = [0; 8192];
+
+    loop {
+        let read = a.read(&mut a_buf)?;
+        if read == 0 {
+            return Ok(true);
+        }
+
+        b.read_exact(&mut b_buf[..read])?;
+        if a_buf[..read] != b_buf[..read] {
+            return Ok(false);
+        }
+    }
+}
+
 enum CreateDirContinuation {
     Exit,
     Continue,
     MaybeRecurse(PathBuf),
 }
 
-fn create_dir(location: &Path, name: &Path, cli: &Cli) -> io::Result<CreateDirContinuation> {
+/// Name the recreated directory `name` gets before any prompt, after `--dir-template`.
+fn dir_name(name: &Path, cli: &Cli) -> io::Result<String> {
+    let Some(template) = cli.dir_template() else {
+        return Ok(name.to_string_lossy().into_owned());
+    };
+
+    // The template was already checked by the argument parser.
+    let rendered = names::render_template(template, &name.to_string_lossy()).unwrap_or_default();
+    names::validate(&rendered).map_err(|err| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("--dir-template gave `{rendered}`: {err}"))
+    })?;
+    Ok(rendered)
+}
+
+fn create_dir(location: &Path, name: &Path, cli: &Cli, run: &mut Run) -> io::Result<CreateDirContinuation> {
     let create = if cli.create_dirs() == Action::Always {
+        explain(cli, &location.join(name), format_args!("creating without asking, --create-dirs is always"));
         Some(true)
     } else {
-        Confirm::new()
+        let answer = Confirm::new()
             .with_prompt(format!(
                 "Recreate the `{}` directory in {}?",
                 name.display(),
-                location.display()
+                cli.show(location)
             ))
-            .default(true)
+            .default(run.confirm_default(cli, Prompt::CreateDir))
             .interact_opt()
-            .map_err(|Error::IO(err)| err)?
+            .map_err(|Error::IO(err)| err)?;
+        run.remember_answer(Prompt::CreateDir, answer);
+        answer
     };
 
     let Some(create) = create else {
@@ -98,147 +769,1976 @@ fn create_dir(location: &Path, name: &Path, cli: &Cli) -> io::Result<CreateDirCo
     };
 
     if !create {
+        run.summary.skipped += 1;
+        run.log(Level::Info, "mkdir", None, Some(&location.join(name)), "declined");
+        explain(cli, &location.join(name), format_args!("not created, declined at the prompt"));
         return Ok(CreateDirContinuation::Continue);
     }
 
-    let dir_name: String = if cli.ask_to_rename_dirs() {
+    let rename = match cli.confirm_dir_name() {
+        Action::Always => Some(true),
+        Action::Never => Some(false),
+        Action::Ask => Confirm::new()
+            .with_prompt(format!("Rename `{}`?", name.display()))
+            .default(false)
+            .interact_opt()
+            .map_err(|Error::IO(err)| err)?,
+    };
+
+    let Some(rename) = rename else {
+        return Ok(CreateDirContinuation::Exit);
+    };
+
+    let proposed_name = dir_name(name, cli)?;
+
+    let dir_name: String = if rename {
         Input::new()
             .with_prompt("Dir name")
-            .with_initial_text(name.to_string_lossy())
+            .with_initial_text(proposed_name)
+            .validate_with(|name: &String| names::validate(name))
             .interact_text()
             .map_err(|Error::IO(err)| err)?
     } else {
-        name.to_string_lossy().into_owned()
+        proposed_name
     };
 
     let new_dir_path = location.join(dir_name);
 
-    fs::create_dir(&new_dir_path)?;
+    // Existing destinations are still reported right away.
+    if cli.lazy_dirs() && new_dir_path.symlink_metadata().is_err() {
+        explain(cli, &new_dir_path, format_args!("not created until a link goes into it, --lazy-dirs is set"));
+        run.pending_dirs.push(new_dir_path.clone());
+    } else if (run.resumed.is_none() && run.index.is_none()) || !new_dir_path.is_dir() {
+        // Directories left by the run being resumed, or by earlier runs with the same --index, are walked again
+        // instead of being reported as existing.
+        make_dir(&new_dir_path, cli, run)?;
+    } else {
+        explain(cli, &new_dir_path, format_args!("reused, it was left by the --resume run or an earlier --index run"));
+    }
 
     Ok(CreateDirContinuation::MaybeRecurse(new_dir_path))
 }
 
+/// Creates the recreated directory `dir`.
+fn make_dir(dir: &Path, cli: &Cli, run: &mut Run) -> io::Result<()> {
+    fs::create_dir(dir)?;
+    set_dir_permissions(dir, cli)?;
+    run.summary.dirs_created += 1;
+    run.created(cli, dir);
+    run.log(Level::Info, "mkdir", None, Some(dir), "created");
+    Ok(())
+}
+
+/// Creates the directories `--lazy-dirs` held back, now that a link goes into the innermost one.
+fn create_pending_dirs(cli: &Cli, run: &mut Run) -> io::Result<()> {
+    for dir in mem::take(&mut run.pending_dirs) {
+        make_dir(&dir, cli, run)?;
+    }
+    Ok(())
+}
+
+/// Applies `--dir-permissions` to a freshly created directory.
+#[cfg(target_family = "unix")]
+fn set_dir_permissions(dir: &Path, cli: &Cli) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match cli.dir_permissions() {
+        Some(mode) => fs::set_permissions(dir, fs::Permissions::from_mode(mode)),
+        None => Ok(()),
+    }
+}
+
+/// `--dir-permissions` is ignored outside Unix, `prepare` warns about that.
+#[cfg(not(target_family = "unix"))]
+fn set_dir_permissions(_dir: &Path, _cli: &Cli) -> io::Result<()> {
+    Ok(())
+}
+
 /// Gets the file type of a directory entry. Follows symbolic links and will therefore never return a link file type.
 fn get_definitive_file_type(entry: &DirEntry) -> io::Result<FileType> {
     Ok(fs::metadata(entry.path())?.file_type())
 }
 
-fn recurse_into_dir(directory: ReadDir, target: &Path, cli: &Cli) -> ShouldExit {
-    for maybe_dir in directory {
-        let entry = match maybe_dir {
-            Ok(dir) => dir,
-            Err(err) => {
-                eprintln!("Failed to open read dir: {err}");
-                continue;
-            }
-        };
-
-        let file_type = match get_definitive_file_type(&entry) {
-            Ok(file_type) => file_type,
-            Err(err) => {
-                eprintln!("Failed to get entry file type: {err}");
-                continue;
-            }
-        };
-
-        if file_type.is_file() {
-            match link_file(&entry.path(), &target.join(entry.file_name()), cli) {
-                Ok(ShouldExit::No) => continue,
-                Ok(ShouldExit::Yes) => return ShouldExit::Yes,
-                Err(err) => {
-                    eprintln!("Encountered error while trying to link file: {err}");
-                    continue;
-                }
-            }
+/// Decides whether to recurse into a recreated directory, asking if needed. `None` if the user wants to exit.
+fn ask_recurse(cli: &Cli, run: &mut Run) -> io::Result<Option<bool>> {
+    match cli.recurse() {
+        Action::Never => Ok(Some(false)),
+        Action::Always => Ok(Some(true)),
+        Action::Ask => {
+            let recurse = Confirm::new()
+                .with_prompt("Should we recurse into the recreated folder?")
+                .default(run.confirm_default(cli, Prompt::Recurse))
+                .interact_opt()
+                .map_err(|Error::IO(err)| err)?;
+            run.remember_answer(Prompt::Recurse, recurse);
+            Ok(recurse)
         }
+    }
+}
 
-        match create_dir(target, Path::new(&entry.file_name()), cli) {
-            Ok(CreateDirContinuation::Exit) => return ShouldExit::Yes,
-            Ok(CreateDirContinuation::Continue) => continue,
-            Ok(CreateDirContinuation::MaybeRecurse(new_dir_path)) => {
-                if cli.recurse() == Action::Never {
-                    continue;
-                }
+/// What to do after looking at one entry of a directory.
+enum Step {
+    Next,
+    Stop(ShouldExit),
+    /// Recurse into the directory, whether it was reached through a symbolic link.
+    Enter(DirEntry, bool),
+}
 
-                let recurse = match cli.recurse() {
-                    Action::Never => continue,
-                    Action::Always => Some(true),
-                    Action::Ask => {
-                        let recurse = Confirm::new()
-                            .with_prompt("Should we recurse into the recreated folder?")
-                            .default(true)
-                            .interact_opt()
-                            .map_err(|Error::IO(err)| err);
-                        if let Err(err) = recurse {
-                            eprintln!("Error in prompt: {err}");
-                            continue;
-                        }
-
-                        recurse.unwrap()
-                    }
-                };
+fn recurse_into_dir(directory: Entries, target: &Path, cli: &Cli, run: &mut Run) -> ShouldExit {
+    if run.workers.is_some() {
+        return thread::scope(|scope| recurse_in_parallel(directory, target, cli, run, scope));
+    }
 
-                if recurse.is_none() {
+    // Files of this directory that made it past the filters, for --per-dir-limit.
+    let mut files = 0;
+
+    for maybe_dir in directory {
+        match entry_step(maybe_dir, target, &mut files, cli, run) {
+            Step::Next => {}
+            Step::Stop(should_exit) => return should_exit,
+            Step::Enter(entry, is_symlink) => {
+                if enter_subtree(&entry, is_symlink, target, cli, run).should_exit() {
                     return ShouldExit::Yes;
                 }
+            }
+        }
+    }
+
+    ShouldExit::No
+}
+
+/// Output and state of a subdirectory walked by a `--parallel-dirs` worker.
+type Subtree = (ShouldExit, Lines, Run);
+
+/// What an entry of a directory walked with `--parallel-dirs` printed, or the worker still walking it.
+enum Slot<'scope> {
+    Done(Lines),
+    Worker(ScopedJoinHandle<'scope, Subtree>),
+}
+
+/// `recurse_into_dir` with `--parallel-dirs`. Subdirectories go to a worker while one is spare and are walked right
+/// away otherwise. What every entry prints is held back until everything before it has been printed.
+fn recurse_in_parallel<'scope, 'env>(
+    directory: Entries,
+    target: &'env Path,
+    cli: &'env Cli,
+    run: &mut Run,
+    scope: &'scope Scope<'scope, 'env>,
+) -> ShouldExit {
+    let mut files = 0;
+    let mut slots = VecDeque::new();
+
+    for maybe_dir in directory {
+        let (step, lines) = output::capture(|| entry_step(maybe_dir, target, &mut files, cli, run));
+        slots.push_back(Slot::Done(lines));
+        let mut end = match step {
+            Step::Next => None,
+            Step::Stop(should_exit) => Some(should_exit),
+            Step::Enter(entry, is_symlink) if run.claim_worker() => {
+                let mut worker = run.fork();
+                slots.push_back(Slot::Worker(scope.spawn(move || {
+                    let (should_exit, lines) =
+                        output::capture(|| enter_subtree(&entry, is_symlink, target, cli, &mut worker));
+                    worker.release_worker();
+                    (should_exit, lines, worker)
+                })));
+                None
+            }
+            Step::Enter(entry, is_symlink) => {
+                let (should_exit, lines) = output::capture(|| enter_subtree(&entry, is_symlink, target, cli, run));
+                slots.push_back(Slot::Done(lines));
+                should_exit.should_exit().then_some(ShouldExit::Yes)
+            }
+        };
+
+        // Like without workers, a subtree stopping the run ends the walk. The workers still running finish first.
+        if flush_slots(&mut slots, false, run).should_exit() {
+            end = Some(ShouldExit::Yes);
+        }
+        if let Some(should_exit) = end {
+            return if flush_slots(&mut slots, true, run).should_exit() { ShouldExit::Yes } else { should_exit };
+        }
+    }
+
+    flush_slots(&mut slots, true, run)
+}
+
+/// Handles one entry of a directory, up to deciding to recurse into it.
+fn entry_step(maybe_dir: io::Result<DirEntry>, target: &Path, files: &mut usize, cli: &Cli, run: &mut Run) -> Step {
+    if run.out_of_time() {
+        return Step::Stop(ShouldExit::Yes);
+    }
+    report_progress(cli, run);
+
+    let entry = match maybe_dir {
+        Ok(dir) => dir,
+        Err(err) => {
+            if walk_error("Failed to open read dir", &err, cli, run).should_exit() {
+                return Step::Stop(ShouldExit::Yes);
+            }
+            return Step::Next;
+        }
+    };
+
+    let is_symlink = entry.file_type().is_ok_and(|file_type| file_type.is_symlink());
+    if is_passed_over(&entry.path(), is_symlink, cli, run) {
+        return Step::Next;
+    }
+
+    let file_type = match get_definitive_file_type(&entry) {
+        Ok(file_type) => file_type,
+        Err(err) => {
+            if walk_error("Failed to get entry file type", &err, cli, run).should_exit() {
+                return Step::Stop(ShouldExit::Yes);
+            }
+            return Step::Next;
+        }
+    };
+
+    if !file_type.is_file() {
+        if let Some(base_device) = &run.base_device {
+            match device::device_of(&entry.path()) {
+                Ok(device) if device != *base_device => {
+                    eprintln!("Skipping `{}`, it's on a different filesystem", cli.show(&entry.path()));
+                    run.summary.skipped += 1;
+                    return Step::Next;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    if walk_error("Failed to get entry device", &err, cli, run).should_exit() {
+                        return Step::Stop(ShouldExit::Yes);
+                    }
+                    return Step::Next;
+                }
+            }
+        }
+    }
+
+    if file_type.is_file() {
+        if let Some(should_exit) = filter_file(&entry.path(), cli, run) {
+            if should_exit.should_exit() {
+                return Step::Stop(ShouldExit::Yes);
+            }
+            return Step::Next;
+        }
+        if over_dir_limit(files, &entry.path(), cli, run) {
+            return Step::Next;
+        }
+    }
+
+    let strategy = if file_type.is_file() {
+        Some(cli.file_strategy(run.relative_source(&entry.path())))
+    } else {
+        cli.dir_link_strategy()
+    };
+
+    if let Some(strategy) = strategy {
+        let link_dir = if file_type.is_file() && !cli.layout().mirrors() {
+            match layout_dir(&entry.path(), target, cli, run) {
+                Ok(CreateDirContinuation::Exit) => return Step::Stop(ShouldExit::Yes),
+                Ok(CreateDirContinuation::Continue) => {
+                    run.summary.skipped += 1;
+                    return Step::Next;
+                }
+                Ok(CreateDirContinuation::MaybeRecurse(group_dir)) => group_dir,
+                Err(err) => {
+                    eprintln!("Failed to create layout folder: {err}");
+                    run.fail(&entry.path());
+                    return Step::Next;
+                }
+            }
+        } else {
+            target.to_path_buf()
+        };
+
+        return match link_file(&entry.path(), &link_dir.join(entry.file_name()), strategy, cli, run) {
+            Ok(ShouldExit::No) if mem::take(&mut run.skip_rest_of_dir) => Step::Stop(ShouldExit::No),
+            Ok(ShouldExit::No) => Step::Next,
+            Ok(ShouldExit::Yes) => Step::Stop(ShouldExit::Yes),
+            Err(err) => {
+                eprintln!("Encountered error while trying to link file: {err}");
+                run.log(Level::Error, "link", Some(&entry.path()), Some(&link_dir), &err.to_string());
+                run.fail(&entry.path());
+                Step::Next
+            }
+        };
+    }
+
+    Step::Enter(entry, is_symlink)
+}
+
+/// `enter_dir` for `entry`, counting it for `--symlink-follow-depth` while inside if it was reached through a
+/// symbolic link.
+fn enter_subtree(entry: &DirEntry, is_symlink: bool, target: &Path, cli: &Cli, run: &mut Run) -> ShouldExit {
+    run.symlinks_followed += usize::from(is_symlink);
+    let should_exit = enter_dir(entry, target, cli, run);
+    run.symlinks_followed -= usize::from(is_symlink);
+    should_exit
+}
+
+/// Prints what the finished entries at the front of `slots` printed and merges what their workers did into `run`.
+/// With `wait` it waits for every worker. Returns whether one of them stopped the run.
+fn flush_slots(slots: &mut VecDeque<Slot>, wait: bool, run: &mut Run) -> ShouldExit {
+    let mut should_exit = ShouldExit::No;
+    while let Some(slot) = slots.pop_front() {
+        match slot {
+            Slot::Done(lines) => output::replay(lines),
+            Slot::Worker(handle) if !wait && !handle.is_finished() => {
+                slots.push_front(Slot::Worker(handle));
+                break;
+            }
+            Slot::Worker(handle) => {
+                let (stopped, lines, worker) = handle.join().unwrap_or_else(|panic| panic::resume_unwind(panic));
+                output::replay(lines);
+                run.absorb(worker);
+                if stopped.should_exit() {
+                    should_exit = ShouldExit::Yes;
+                }
+            }
+        }
+    }
+
+    should_exit
+}
+
+/// Checks whether `path` is skipped before even looking at what it is, as the `--find-root` marker or as a
+/// symbolic link beyond `--symlink-follow-depth`, and counts it if so.
+fn is_passed_over(path: &Path, is_symlink: bool, cli: &Cli, run: &mut Run) -> bool {
+    // The marker may well be a directory, like `.git`.
+    let why = if cli.is_root_marker(path) {
+        "it's the --find-root marker"
+    } else if is_symlink && cli.symlink_follow_depth().is_some_and(|max| run.symlinks_followed >= max) {
+        "following it would exceed --symlink-follow-depth"
+    } else {
+        return false;
+    };
+
+    explain(cli, path, format_args!("skipped, {why}"));
+    run.summary.skipped += 1;
+    true
+}
+
+/// Applies the filters that decide whether `file` gets linked at all.
+///
+/// Returns `Some` if the file shouldn't be linked.
+fn filter_file(file: &Path, cli: &Cli, run: &mut Run) -> Option<ShouldExit> {
+    if cli.skip_zero_byte_files() {
+        match run.source_metadata(cli, file) {
+            Ok(metadata) if metadata.len() == 0 => {
+                explain(cli, file, format_args!("skipped, it's empty and --skip-zero-byte-files is set"));
+                run.summary.skipped += 1;
+                return Some(ShouldExit::No);
+            }
+            Ok(_) => {}
+            Err(err) => return Some(walk_error("Failed to get entry size", &err, cli, run)),
+        }
+    }
+
+    if let Some(threshold) = cli.multilink_threshold() {
+        match run.source_metadata(cli, file) {
+            Ok(metadata) if link::hard_link_count(&metadata).is_some_and(|count| count >= threshold) => {
+                explain(cli, file, format_args!("skipped, it's hard linked elsewhere and --skip-multilinked is set"));
+                run.summary.skipped += 1;
+                return Some(ShouldExit::No);
+            }
+            Ok(_) => {}
+            Err(err) => return Some(walk_error("Failed to get entry link count", &err, cli, run)),
+        }
+    }
+
+    if cli.min_age().is_some() || cli.max_age().is_some() {
+        match run.source_metadata(cli, file).and_then(|metadata| metadata.modified()) {
+            Ok(modified) if !cli.within_age(modified) => {
+                explain(cli, file, format_args!("skipped, it's outside --min-age and --max-age"));
+                run.summary.skipped += 1;
+                return Some(ShouldExit::No);
+            }
+            Ok(_) => {}
+            Err(err) => return Some(walk_error("Failed to get entry modification time", &err, cli, run)),
+        }
+    }
+
+    if let Some(filter) = cli.filter_script() {
+        match filter.allows(file) {
+            Ok(Some(true)) => {}
+            Ok(Some(false)) => {
+                explain(cli, file, format_args!("skipped, rejected by --filter-script"));
+                run.summary.skipped += 1;
+                return Some(ShouldExit::No);
+            }
+            Ok(None) => {
+                eprintln!("--filter-script aborted the run at `{}`", cli.show(file));
+                return Some(ShouldExit::Yes);
+            }
+            Err(err) => {
+                eprintln!("Failed to run --filter-script: {err}");
+                run.fail(file);
+                return Some(ShouldExit::No);
+            }
+        }
+    }
+
+    if let Some(wait) = cli.require_stable() {
+        match is_stable(file, wait) {
+            Ok(true) => {}
+            Ok(false) => {
+                explain(cli, file, format_args!("skipped, it changed within --require-stable"));
+                run.summary.skipped += 1;
+                run.unstable.push(file.to_path_buf());
+                return Some(ShouldExit::No);
+            }
+            Err(err) => return Some(walk_error("Failed to check whether entry is stable", &err, cli, run)),
+        }
+    }
+
+    None
+}
+
+/// Whether the size and modification time of `file` stay the same for `wait`, meaning nothing is writing to it.
+fn is_stable(file: &Path, wait: Duration) -> io::Result<bool> {
+    // Bypasses `--inode-cache`, cached metadata would always look stable.
+    let before = fs::metadata(file)?;
+    thread::sleep(wait);
+    let after = fs::metadata(file)?;
+
+    Ok(before.len() == after.len() && before.modified()? == after.modified()?)
+}
+
+/// Counts an entry of the walk, printing the counts so far every `--batch-size` entries and saving progress every
+/// `--checkpoint-every` entries.
+fn report_progress(cli: &Cli, run: &mut Run) {
+    let every = |n: Option<u64>| run.processed > 0 && n.is_some_and(|n| run.processed.is_multiple_of(n));
+
+    if every(cli.batch_size()) {
+        eprintln!("After {} entries: {}", run.processed, run.summary.line());
+    }
+    if every(cli.checkpoint_every()) {
+        checkpoint(cli, run);
+    }
+    run.processed += 1;
+}
+
+/// Saves the `--index` and syncs the `--resume` file for `--checkpoint-every`, only warning if that fails.
+fn checkpoint(cli: &Cli, run: &Run) {
+    if let (Some(index_path), Some(index)) = (cli.index(), &run.index) {
+        if let Err(err) = index.save(index_path) {
+            eprintln!("Failed to save index `{}` at a checkpoint: {err}", index_path.display());
+        }
+    }
+    if let Some(journal) = &run.journal {
+        if let Err(err) = journal.sync() {
+            eprintln!("Failed to sync the --resume file at a checkpoint: {err}");
+        }
+    }
+}
+
+/// Counts `file` towards `--per-dir-limit` and checks whether its directory already hit the limit, warning the first
+/// time it does.
+fn over_dir_limit(files: &mut usize, file: &Path, cli: &Cli, run: &mut Run) -> bool {
+    let Some(limit) = cli.per_dir_limit() else {
+        return false;
+    };
+
+    if *files == limit {
+        let dir = file.parent().unwrap_or(file);
+        eprintln!("`{}` has more than {limit} files, skipping the rest of them", cli.show(dir));
+    }
+    *files += 1;
+
+    if *files > limit {
+        explain(cli, file, format_args!("skipped, its directory is over --per-dir-limit"));
+        run.summary.skipped += 1;
+        return true;
+    }
+
+    false
+}
+
+/// Recreates the directory `entry` in `target` and recurses into it if wanted.
+fn enter_dir(entry: &DirEntry, target: &Path, cli: &Cli, run: &mut Run) -> ShouldExit {
+    // With --layout files land in the layout's folders of <TARGET>, so directories aren't recreated.
+    let existing = target.join(entry.file_name());
+    if cli.merge() && existing.is_dir() {
+        explain(cli, &existing, format_args!("entering the existing directory, --merge is set"));
+        return match walk_dir(&entry.path(), &existing, cli, run) {
+            Ok(should_exit) => should_exit,
+            Err(err) => walk_error("Failed to recurse into directory", &err, cli, run),
+        };
+    }
+
+    let new_dir_path = if cli.layout().mirrors() {
+        match create_dir(target, Path::new(&entry.file_name()), cli, run) {
+            Ok(CreateDirContinuation::Exit) => return ShouldExit::Yes,
+            Ok(CreateDirContinuation::Continue) => return ShouldExit::No,
+            Ok(CreateDirContinuation::MaybeRecurse(new_dir_path)) => new_dir_path,
+            Err(err) => {
+                eprintln!("Failed to create file: {err}");
+                run.log(Level::Error, "mkdir", None, Some(&target.join(entry.file_name())), &err.to_string());
+                run.fail(&entry.path());
+                return ShouldExit::No;
+            }
+        }
+    } else {
+        target.to_path_buf()
+    };
+
+    let recurse = match ask_recurse(cli, run) {
+        Ok(recurse) => recurse,
+        Err(err) => {
+            eprintln!("Error in prompt: {err}");
+            run.summary.failed += 1;
+            return ShouldExit::No;
+        }
+    };
+
+    let should_exit = match recurse {
+        None => ShouldExit::Yes,
+        Some(false) => ShouldExit::No,
+        Some(true) => match walk_dir(&entry.path(), &new_dir_path, cli, run) {
+            Ok(should_exit) => should_exit,
+            Err(err) => walk_error("Failed to recurse into directory", &err, cli, run),
+        },
+    };
+
+    if run.pending_dirs.last() == Some(&new_dir_path) {
+        // Nothing was linked into it, so with --lazy-dirs it's never created.
+        run.pending_dirs.pop();
+    }
+    should_exit
+}
+
+/// Finds the `--layout` folder of `target` that `file` goes into, creating it the first time it's needed.
+fn layout_dir(file: &Path, target: &Path, cli: &Cli, run: &mut Run) -> io::Result<CreateDirContinuation> {
+    let metadata = run.source_metadata(cli, file)?;
+    let group = cli.layout().dest_dir(run.relative_source(file), &metadata);
+    if group.as_os_str().is_empty() {
+        return Ok(CreateDirContinuation::MaybeRecurse(target.to_path_buf()));
+    }
+    let group_path = target.join(&group);
+
+    if let Some(group_dir) = run.groups.get(&group_path) {
+        return Ok(match group_dir {
+            Some(group_dir) => CreateDirContinuation::MaybeRecurse(group_dir.clone()),
+            None => CreateDirContinuation::Continue,
+        });
+    }
+
+    let continuation = if group_path.is_dir() {
+        CreateDirContinuation::MaybeRecurse(group_path.clone())
+    } else {
+        create_dir(target, &group, cli, run)?
+    };
+
+    match &continuation {
+        CreateDirContinuation::Exit => {}
+        CreateDirContinuation::Continue => {
+            run.groups.insert(group_path, None);
+        }
+        CreateDirContinuation::MaybeRecurse(group_dir) => {
+            run.groups.insert(group_path, Some(group_dir.clone()));
+        }
+    }
+
+    Ok(continuation)
+}
+
+/// Reports an error hit while walking <BASE>, handling denied permissions according to `--on-permission-denied`.
+fn walk_error(message: &str, err: &io::Error, cli: &Cli, run: &mut Run) -> ShouldExit {
+    let on_denied = if err.kind() == io::ErrorKind::PermissionDenied {
+        cli.on_permission_denied()
+    } else {
+        OnPermissionDenied::Warn
+    };
+
+    match on_denied {
+        OnPermissionDenied::Warn => {
+            eprintln!("{message}: {err}");
+            run.summary.failed += 1;
+            ShouldExit::No
+        }
+        OnPermissionDenied::SkipTree => {
+            run.summary.skipped += 1;
+            ShouldExit::No
+        }
+        OnPermissionDenied::Fail => {
+            eprintln!("{message}: {err}, stopping");
+            run.summary.failed += 1;
+            run.failed_fatally = true;
+            ShouldExit::Yes
+        }
+    }
+}
+
+/// Recurses into `dir`, keeping it open only while that stays within `--max-open-dirs`.
+fn walk_dir(dir: &Path, target: &Path, cli: &Cli, run: &mut Run) -> io::Result<ShouldExit> {
+    let select = cli.multiselect_per_dir() && cli.create_links() == Action::Ask;
+    let reorder = cli.within_dir_order() != WithinDirOrder::Read;
+    let open = !select && !reorder && run.claim_open_dir(cli.max_open_dirs());
+    let mut entries = Entries::read(dir, !open).inspect_err(|_| {
+        if open {
+            run.release_open_dir();
+        }
+    })?;
+    if reorder {
+        entries = order_entries(entries, cli.within_dir_order() == WithinDirOrder::DirsFirst);
+    }
+    if select {
+        match select_files(dir, entries, cli, run)? {
+            Some(selected) => entries = selected,
+            None => return Ok(ShouldExit::Yes),
+        }
+    }
+
+    let should_exit = recurse_into_dir(entries, target, cli, run);
+    if open {
+        run.release_open_dir();
+    }
+    if select {
+        // Files a filter skipped never got to use their answer.
+        run.selection.retain(|file, _| file.parent() != Some(dir));
+    }
+
+    // Directories nothing was linked into keep their modification time.
+    if run.written_dirs.remove(target) {
+        touch_dir(target, cli);
+    }
+
+    Ok(should_exit)
+}
+
+/// Touches the directories links went into that weren't walked, like `--layout` folders, once the run is done.
+/// Deepest first, so `--touch-from-children` sees the final times of subdirectories.
+fn touch_written_dirs(cli: &Cli, run: &mut Run) {
+    let mut dirs: Vec<PathBuf> = run.written_dirs.drain().collect();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in dirs {
+        touch_dir(&dir, cli);
+    }
+}
+
+/// Moves the directories in `entries` in front of everything else with `dirs_first`, behind it otherwise. Keeps the
+/// order among files and among directories. Entries that can't be read stay with the files and fail when handled.
+fn order_entries(entries: Entries, dirs_first: bool) -> Entries {
+    let mut entries = entries.collect::<Vec<_>>();
+    entries.sort_by_cached_key(|entry| {
+        let is_dir = entry
+            .as_ref()
+            .is_ok_and(|entry| get_definitive_file_type(entry).is_ok_and(|file_type| file_type.is_dir()));
+        is_dir != dirs_first
+    });
+
+    Entries::Buffered(entries.into_iter())
+}
+
+/// Asks which files of `dir` to link with a single `--multiselect-per-dir` list, remembering the answers in
+/// `run.selection`. Returns the entries again for walking them, `None` if the user wants to exit.
+fn select_files(dir: &Path, entries: Entries, cli: &Cli, run: &mut Run) -> io::Result<Option<Entries>> {
+    let entries = entries.collect::<Vec<_>>();
+    let files = entries
+        .iter()
+        .flatten()
+        .filter(|entry| get_definitive_file_type(entry).is_ok_and(|file_type| file_type.is_file()))
+        .map(DirEntry::path)
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Ok(Some(Entries::Buffered(entries.into_iter())));
+    }
+
+    let names = files
+        .iter()
+        .map(|file| file.file_name().unwrap_or_default().to_string_lossy())
+        .collect::<Vec<_>>();
+    let default = run.confirm_default(cli, Prompt::CreateLink);
+    let Some(picked) = MultiSelect::new()
+        .with_prompt(format!("Link which files of `{}`? Space toggles, enter confirms", cli.show(dir)))
+        .items(&names)
+        .defaults(&vec![default; names.len()])
+        .interact_opt()
+        .map_err(|Error::IO(err)| err)?
+    else {
+        return Ok(None);
+    };
+
+    for (index, file) in files.into_iter().enumerate() {
+        run.selection.insert(file, picked.contains(&index));
+    }
+    Ok(Some(Entries::Buffered(entries.into_iter())))
+}
+
+/// Applies `--touch-target-dirs` to `dir`, only warning if that fails.
+fn touch_dir(dir: &Path, cli: &Cli) {
+    if let Err(err) = set_dir_mtime(dir, cli.touch_from_children()) {
+        eprintln!("Failed to update the modification time of `{}`: {err}", cli.show(dir));
+    }
+}
+
+/// Sets the modification time of `dir` to now, or with `from_children` to that of its newest entry.
+fn set_dir_mtime(dir: &Path, from_children: bool) -> io::Result<()> {
+    let mut mtime = SystemTime::now();
+    if from_children {
+        let mut newest = None;
+        for entry in dir.read_dir()? {
+            let modified = entry?.path().symlink_metadata()?.modified()?;
+            newest = newest.max(Some(modified));
+        }
+        // An empty directory has no children to take a time from.
+        mtime = newest.unwrap_or(mtime);
+    }
+
+    open_dir_for_times(dir)?.set_modified(mtime)
+}
+
+#[cfg(target_family = "unix")]
+fn open_dir_for_times(dir: &Path) -> io::Result<File> {
+    File::open(dir)
+}
+
+#[cfg(target_family = "windows")]
+fn open_dir_for_times(dir: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // Directories can only be opened with backup semantics, and only need the right to change attributes.
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x0100;
+
+    File::options()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(dir)
+}
+
+/// Expands <BASE> into every base it matches.
+fn resolve_bases(cli: &Cli) -> Result<Vec<PathBuf>, String> {
+    let mut bases = if cli.base.exists() {
+        vec![cli.base.clone()]
+    } else {
+        let bases = glob::expand(&cli.base).map_err(|err| format!("Failed to expand <BASE> pattern: {err}"))?;
+        if bases.is_empty() {
+            return Err(format!("<BASE> pattern `{}` didn't match anything", cli.base.display()));
+        }
+
+        bases
+    };
+
+    if !cli.follow_base_symlink() {
+        for base in &bases {
+            let is_symlink = base
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.file_type().is_symlink());
+            if is_symlink {
+                let real = fs::canonicalize(base).unwrap_or_else(|_| base.clone());
+                return Err(format!(
+                    "<BASE> `{}` is a symbolic link to `{}`, pass the real path instead",
+                    base.display(),
+                    real.display()
+                ));
+            }
+        }
+    }
+
+    if cli.absolute_base() {
+        for base in &mut bases {
+            *base = absolute(base).map_err(|err| format!("Failed to make `{}` absolute: {err}", base.display()))?;
+        }
+    }
+
+    Ok(bases)
+}
+
+/// Canonicalizes `path`. If it doesn't exist only its parent is canonicalized.
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound && path.file_name().is_some() => canonical_parent(path),
+        result => result,
+    }
+}
+
+/// Canonicalizes the directory containing `path`, keeping its last component as is in case that's a link itself.
+fn canonical_parent(path: &Path) -> io::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("`{}` has no file name", path.display())))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    Ok(fs::canonicalize(parent)?.join(file_name))
+}
+
+/// Expresses the absolute `path` relative to the absolute directory `dir`. `None` if they don't share a root.
+fn relative_to(path: &Path, dir: &Path) -> Option<PathBuf> {
+    let mut path_components = path.components().peekable();
+    let mut dir_components = dir.components().peekable();
+    // Paths on different Windows drives can't be relative to each other.
+    if path_components.next() != dir_components.next() {
+        return None;
+    }
+
+    while path_components.peek().is_some() && path_components.peek() == dir_components.peek() {
+        path_components.next();
+        dir_components.next();
+    }
+
+    let mut relative: PathBuf = dir_components.map(|_| Component::ParentDir).collect();
+    relative.extend(path_components);
+    Some(relative)
+}
+
+/// Path the symbolic link `link` should store to point at `original`, according to `--rebase-symlinks` and
+/// `--symlink-policy`.
+fn symlink_source(original: &Path, link: &Path, cli: &Cli) -> io::Result<PathBuf> {
+    let original = if cli.rebase_symlinks() && original.symlink_metadata()?.file_type().is_symlink() {
+        fs::canonicalize(original)?
+    } else {
+        original.to_path_buf()
+    };
+    let Some(policy) = cli.symlink_policy() else {
+        return Ok(original);
+    };
+
+    let source = canonical_parent(&original)?;
+    let relative = match policy {
+        SymlinkPolicy::Relative => true,
+        SymlinkPolicy::Absolute => false,
+        SymlinkPolicy::Hybrid => source.starts_with(fs::canonicalize(cli.target_dir())?),
+    };
+    if !relative {
+        return Ok(source);
+    }
+
+    // `canonical_parent` only ever appends the file name, so the canonical link directory is its parent.
+    let canonical = canonical_parent(link)?;
+    let link_dir = canonical.parent().unwrap_or(&canonical);
+    let Some(relative) = relative_to(&source, link_dir) else {
+        return Ok(source);
+    };
+
+    let updepth = relative.components().filter(|component| *component == Component::ParentDir).count();
+    if cli.relative_max_updepth().is_some_and(|max| updepth > max) {
+        eprintln!(
+            "Linking `{}` with an absolute path, the relative one climbs {updepth} levels, more than --relative-max-updepth",
+            cli.show(link)
+        );
+        return Ok(source);
+    }
+    Ok(relative)
+}
+
+/// Checks whether `path` is a `--write-provenance` sidecar sitting next to its link.
+fn is_sidecar(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == provenance::EXTENSION)
+        && path.with_extension("").symlink_metadata().is_ok()
+}
+
+/// Applies `--retarget-prefix` to the path a symbolic link should store.
+fn retarget(points_to: PathBuf, cli: &Cli) -> io::Result<PathBuf> {
+    let Some((from, to)) = cli.retarget_prefix() else {
+        return Ok(points_to);
+    };
+
+    match points_to.strip_prefix(from) {
+        Ok(rest) => Ok(to.join(rest)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "`{}` doesn't start with the --retarget-prefix `{}`",
+                points_to.display(),
+                from.display()
+            ),
+        )),
+    }
+}
+
+/// Reports or removes entries in <TARGET> without a source according to `--on-orphan`. What the run created is never
+/// an orphan, even if it was renamed at a prompt.
+fn handle_orphans(bases: &[PathBuf], cli: &Cli, run: &mut Run) -> io::Result<()> {
+    let ignore: Vec<&Path> = cli.backup_dir().into_iter().chain(cli.provenance_dir()).collect();
+    let expected = expected_target(bases, cli, run)?;
+    let mut paths = expected.paths();
+    paths.extend(run.created.iter().map(PathBuf::as_path));
+
+    for orphan in orphans::find(&cli.target, &paths, &ignore)? {
+        if cli.write_provenance() && is_sidecar(&orphan) {
+            continue;
+        }
+
+        if cli.on_orphan() == OnOrphan::Report {
+            println!("No source for `{}`", cli.show(&orphan));
+            continue;
+        }
+
+        if !cli.remove_orphan_files() && !orphans::is_link(&orphan)? {
+            println!("No source for `{}`, not removing it since it isn't a link", cli.show(&orphan));
+            continue;
+        }
+
+        let remove = if cli.assume_yes() {
+            Some(true)
+        } else {
+            Confirm::new()
+                .with_prompt(format!("`{}` has no source. Remove it?", cli.show(&orphan)))
+                .default(false)
+                .interact_opt()
+                .map_err(|Error::IO(err)| err)?
+        };
+
+        match remove {
+            None => break,
+            Some(false) => {}
+            Some(true) => {
+                orphans::remove(&orphan)?;
+                println!("Removed `{}`", cli.show(&orphan));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Works out what linking `bases` puts into <TARGET>, named and placed the way the walk names and places it.
+fn expected_target(bases: &[PathBuf], cli: &Cli, run: &mut Run) -> io::Result<mirror::Expected> {
+    let mut expected = mirror::Expected::default();
+    for base in bases {
+        // `--name-map` is relative to the base.
+        run.base.clone_from(base);
+        let run = &*run;
+        let file_name = |source: &Path, link: &Path| link_name(source, link, cli, run, false);
+        let dir_name = |name: &OsStr| dir_name(Path::new(name), cli).map_or_else(|_| name.to_owned(), OsString::from);
+        let naming = mirror::Naming {
+            layout: cli.layout(),
+            flatten: cli.flatten_naming(),
+            link_dirs: cli.dir_link_strategy().is_some(),
+            file_name: &file_name,
+            dir_name: &dir_name,
+        };
+        mirror::expect(base, &base_target(base, cli), &naming, &mut expected)?;
+    }
+
+    Ok(expected)
+}
+
+/// Resolves and validates <BASE> and <TARGET> and runs the checks that have to pass before linking starts.
+fn prepare(cli: &mut Cli) -> Result<Vec<PathBuf>, String> {
+    let bases = resolve_bases(cli)?;
+
+    if cli.absolute_base() {
+        cli.target = absolute(&cli.target).map_err(|err| format!("Failed to make <TARGET> absolute: {err}"))?;
+    }
+
+    cli.set_output_bases(&bases);
+
+    let single_file = bases.len() == 1 && bases[0].is_file();
+    if cli.target_is_file() && !single_file {
+        return Err("--target-is-file requires <BASE> to be a single file".to_string());
+    }
+    if !single_file && !cli.target.is_dir() {
+        return Err("<TARGET> is not a directory!".to_string());
+    }
+    if cli.atomic_run() && !cli.target.is_dir() {
+        return Err("--atomic-run requires <TARGET> to be a directory".to_string());
+    }
+
+    if !cli.allow_dangerous_target() {
+        preflight::check_target_allowed(cli.target_dir(), cli.forbid_target())?;
+    }
+
+    if !cli.layout().mirrors() && (cli.merge() || cli.on_orphan() != OnOrphan::Ignore) {
+        return Err("--merge and orphan handling need <TARGET> to mirror <BASE>, drop --layout".to_string());
+    }
+
+    if cli.checkpoint_every().is_some() && cli.index().is_none() && cli.resume().is_none() {
+        return Err("--checkpoint-every needs --index or --resume to save progress to".to_string());
+    }
+
+    if let (Some(min), Some(max)) = (cli.min_age(), cli.max_age()) {
+        if min > max {
+            return Err("--min-age can't be larger than --max-age".to_string());
+        }
+    }
+
+    if cli.require_owner() {
+        preflight::check_owner(cli.target_dir())?;
+    }
+
+    // --count-only, --audit and --diff-against never write to <TARGET>.
+    if !cli.no_writability_check() && cli.writes_target() {
+        let modified = fs::metadata(cli.target_dir()).and_then(|metadata| metadata.modified());
+        preflight::check_writable(cli.target_dir())?;
+        // The probe file shouldn't make <TARGET> look changed to anything watching its modification time.
+        if let Ok(modified) = modified {
+            let _ = open_dir_for_times(cli.target_dir()).and_then(|dir| dir.set_modified(modified));
+        }
+    }
+
+    if cli.link_strategy() == LinkStrategy::Hard && cli.writes_target() && !cli.auto_copy_cross_device() {
+        preflight::check_same_device(&bases, cli.target_dir())?;
+    }
+
+    if cli.writes_target() {
+        let symlinks = cli.link_strategy().is_symbolic() || cli.dir_link_strategy().is_some();
+        let hard_links = cli.link_strategy() == LinkStrategy::Hard;
+        match preflight::check_link_support(cli.target_dir(), symlinks, hard_links) {
+            Err(err) if cli.strict_fs() => return Err(err),
+            Err(err) => eprintln!("{err}"),
+            Ok(()) => {}
+        }
+    }
+
+    if cli.estimate_copy_size() && cli.writes_target() {
+        check_copy_size(&bases, cli)?;
+    }
+
+    if let Some(threshold) = cli.auto_confirm_under() {
+        // An aborted count says nothing about the size of the run, so keep prompting then.
+        if let (counts, true) = count::count(&bases, cli)? {
+            if counts.links < threshold {
+                cli.confirm_everything();
+            }
+        }
+    }
+
+    if cli.move_source() && cli.writes_target() {
+        confirm_move_source(cli)?;
+    }
+
+    if cli.writes_target() && cli.may_prompt() && !can_prompt() {
+        if cli.noninteractive_fallback() == NoninteractiveFallback::AssumeYes {
+            cli.confirm_everything();
+        }
+        if cli.may_prompt() {
+            return Err(
+                "Prompts require a terminal. Use -f and -y or --noninteractive-fallback assume-yes, and drop flags \
+                 asking for prompts like `--recurse ask`"
+                    .to_string(),
+            );
+        }
+    }
+
+    // Workers can't share a terminal, or the `--layout` folders they'd all link into.
+    if cli.parallel_dirs().is_some() && cli.writes_target() && (cli.may_prompt() || !cli.layout().mirrors()) {
+        return Err("--parallel-dirs can't be combined with prompts or --layout, use -f and -y".to_string());
+    }
+
+    let makes_symlinks = cli.link_strategy().is_symbolic() || cli.dir_link_strategy().is_some();
+    if cli.retarget_prefix().is_some() && !makes_symlinks {
+        eprintln!("--retarget-prefix only affects symbolic links, ignoring it");
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    if cli.dir_permissions().is_some() {
+        eprintln!("--dir-permissions is only supported on Unix, ignoring it");
+    }
+    #[cfg(not(target_family = "unix"))]
+    if cli.multilink_threshold().is_some() {
+        eprintln!("--skip-multilinked is only supported on Unix, ignoring it");
+    }
+
+    Ok(bases)
+}
+
+/// Estimates how much `--estimate-copy-size` will copy and checks that it fits into <TARGET>.
+fn check_copy_size(bases: &[PathBuf], cli: &Cli) -> Result<(), String> {
+    if !cli.may_copy() {
+        eprintln!("--estimate-copy-size: nothing is copied, skipping the estimate");
+        return Ok(());
+    }
+
+    let (counts, complete) = count::count(bases, cli)?;
+    if !complete {
+        eprintln!("--filter-script aborted the estimate, it only covers the files before that");
+    }
+    if counts.unreadable > 0 {
+        // Those fail to link in the run as well, so they don't need space either.
+        eprintln!("The estimate leaves out {} entries that couldn't be read", counts.unreadable);
+    }
+    let needed = size::format(counts.bytes_to_copy);
+    let free = match preflight::free_space(cli.target_dir()) {
+        Ok(free) => free,
+        Err(err) => {
+            eprintln!("Estimated copy size: {needed} in {} files. {err}", counts.copies);
+            return Ok(());
+        }
+    };
+
+    eprintln!("Estimated copy size: {needed} in {} files, {} free in <TARGET>", counts.copies, size::format(free));
+    if counts.bytes_to_copy > free {
+        return Err(format!(
+            "`{}` doesn't have enough free space for the copies, refusing to start",
+            cli.target_dir().display()
+        ));
+    }
+    Ok(())
+}
 
-                if !recurse.unwrap() {
-                    continue;
-                }
+/// Makes sure `--move-source` can do something and that the user really wants sources deleted.
+fn confirm_move_source(cli: &Cli) -> Result<(), String> {
+    if cli.link_strategy() != LinkStrategy::Copy && !cli.copies_any() {
+        return Err("--move-source only moves copied files, use --link-strategy copy or --copy-matching".to_string());
+    }
+    if !can_prompt() {
+        return Err("--move-source has to be confirmed in a terminal".to_string());
+    }
 
-                let recurse_dirs = match entry.path().read_dir() {
-                    Ok(recurse_dirs) => recurse_dirs,
-                    Err(err) => {
-                        eprintln!("Failed to recurse into directory: {err}");
-                        continue;
-                    }
-                };
+    let confirmed = Confirm::new()
+        .with_prompt("--move-source deletes every source after it was copied. Continue?")
+        .default(false)
+        .interact_opt()
+        .map_err(|Error::IO(err)| format!("Error in prompt: {err}"))?;
+    if confirmed == Some(true) {
+        Ok(())
+    } else {
+        Err("Not moving any sources".to_string())
+    }
+}
 
-                if recurse_into_dir(recurse_dirs, &new_dir_path, cli).should_exit() {
-                    return ShouldExit::Yes;
-                }
+/// Checks whether prompts can be shown, which needs a terminal to read answers from and draw the prompt on.
+fn can_prompt() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Where `base` is linked: <TARGET> for a directory, the link itself for a single file.
+fn base_target(base: &Path, cli: &Cli) -> PathBuf {
+    if base.is_dir() || cli.target_is_file() || !cli.target.is_dir() {
+        return cli.target.clone();
+    }
+
+    let base_file_name = base
+        .file_name()
+        .expect("<BASE> was provided a file that doesn't have a valid filename by Rust rules");
+    cli.target.join(base_file_name)
+}
+
+/// Links a single base into <TARGET>. Returns an error if the base couldn't be handled at all.
+fn link_base(base: &Path, cli: &Cli, run: &mut Run) -> Result<ShouldExit, String> {
+    run.base = base.to_path_buf();
+
+    if base.is_file() {
+        let link = base_target(base, cli);
+        return link_file(base, &link, cli.file_strategy(run.relative_source(base)), cli, run)
+            .map_err(|err| format!("Encountered and error while handling file: {err}"));
+    }
+
+    run.base_device = if cli.one_file_system() {
+        Some(device::device_of(base).map_err(|err| format!("Failed to get <BASE> device: {err}"))?)
+    } else {
+        None
+    };
+
+    walk_dir(base, &cli.target, cli, run)
+        .map_err(|err| format!("Failed to read <BASE> dir `{}`: {err}", base.display()))
+}
+
+/// Sets up the state of a run, loading the files it needs.
+fn start_run(cli: &Cli) -> Result<Run, String> {
+    let mut run = Run::default();
+    run.started = audit::timestamp(SystemTime::now());
+    run.deadline = cli.timeout_total().map(|timeout| Instant::now() + timeout);
+    run.summary.bytes_linked = cli.report_bytes().then_some(0);
+    load_names(cli, &mut run)?;
+    // The run itself walks as well.
+    run.workers = cli
+        .parallel_dirs()
+        .filter(|&workers| workers > 1)
+        .map(|workers| Arc::new(AtomicUsize::new(workers - 1)));
+    if cli.auto_copy_cross_device() {
+        run.target_device = Some(
+            device::device_of(cli.target_dir())
+                .map_err(|err| format!("Failed to get the device of <TARGET>: {err}"))?,
+        );
+    }
+
+    if let Some(index_path) = cli.index() {
+        let index = Index::load(index_path)
+            .map_err(|err| format!("Failed to load index `{}`: {err}", index_path.display()))?;
+        run.index = Some(index);
+    }
+
+    if let Some(resume) = cli.resume() {
+        let resumed =
+            Index::load(resume).map_err(|err| format!("Failed to load resume file `{}`: {err}", resume.display()))?;
+        run.resumed = Some(resumed);
+        run.journal = Some(
+            Journal::open(resume).map_err(|err| format!("Failed to open resume file `{}`: {err}", resume.display()))?,
+        );
+    }
+
+    if let Some(log_file) = cli.log_file() {
+        run.audit_log = Some(
+            AuditLog::open(log_file)
+                .map_err(|err| format!("Failed to open log file `{}`: {err}", log_file.display()))?,
+        );
+    }
+
+    if let Some(remember_answers) = cli.remember_answers().filter(|_| !cli.forget()) {
+        run.remembered = answers::load(remember_answers)
+            .map_err(|err| format!("Failed to load answers `{}`: {err}", remember_answers.display()))?;
+    }
+
+    Ok(run)
+}
+
+/// Loads what naming links needs, the `--name-map` and whether <TARGET> restricts names for `--sanitize-names`.
+fn load_names(cli: &Cli, run: &mut Run) -> Result<(), String> {
+    run.restricted_names = cli.sanitize_names() && preflight::restricts_names(cli.target_dir());
+    if let Some(name_map) = cli.name_map() {
+        run.name_map = names::load_map(name_map)
+            .map_err(|err| format!("Failed to load name map `{}`: {err}", name_map.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Points <TARGET> at a fresh staging directory for `--atomic-run`. Returns the real <TARGET> if it did.
+fn stage_target(cli: &mut Cli, run: &mut Run) -> io::Result<Option<PathBuf>> {
+    if !cli.atomic_run() {
+        return Ok(None);
+    }
+
+    let staging = atomic::stage(&cli.target)?;
+    run.staging = Some((staging.clone(), cli.target.clone()));
+    Ok(Some(mem::replace(&mut cli.target, staging)))
+}
+
+/// Swaps the staging directory of `--atomic-run` into place, or discards it if the run didn't `complete`.
+fn finish_atomic_run(staging: &Path, target: &Path, complete: bool) -> Result<(), String> {
+    if complete {
+        atomic::swap(staging, target)
+            .map_err(|err| format!("Failed to swap `{}` into place: {err}", staging.display()))
+    } else {
+        atomic::roll_back(staging)
+            .map_err(|err| format!("Failed to remove staging directory `{}`: {err}", staging.display()))?;
+        Err(format!("Run didn't complete, left `{}` untouched", target.display()))
+    }
+}
+
+/// Writes the sources that failed to link to `path`, one per line or NUL terminated with `nul`.
+fn write_failures(path: &Path, failures: &[PathBuf], nul: bool) -> io::Result<()> {
+    let separator = if nul { '\0' } else { '\n' };
+    let mut content = String::new();
+    for failure in failures {
+        content.push_str(&failure.to_string_lossy());
+        content.push(separator);
+    }
+
+    fs::write(path, content)
+}
+
+/// Prints how many links and directories a run over `bases` would create, without prompting or touching <TARGET>.
+fn count_only(bases: &[PathBuf], cli: &Cli) -> ExitCode {
+    let counts = match count::count(bases, cli) {
+        Ok((counts, complete)) => {
+            if !complete {
+                eprintln!("--filter-script aborted the count");
             }
+            counts
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::from(EXIT_SETUP_FAILED);
+        }
+    };
+
+    println!("Links to create: {}", counts.links);
+    println!("Directories to create: {}", counts.dirs);
+    ExitCode::SUCCESS
+}
+
+/// Compares <TARGET> with <BASE> for `--audit`, reporting every entry out of sync without changing anything.
+fn audit_mirror(bases: &[PathBuf], cli: &Cli) -> ExitCode {
+    let is_linked = |source: &Path, dest: &Path, relative: &Path| {
+        links_to(dest, source)
+            || index::same_file(dest, source)
+            || (cli.file_strategy(relative) == LinkStrategy::Copy && has_same_content(source, dest).unwrap_or(false))
+    };
+
+    let mut run = Run::default();
+    if let Err(err) = load_names(cli, &mut run) {
+        eprintln!("{err}");
+        return ExitCode::from(EXIT_SETUP_FAILED);
+    }
+    let expected = match expected_target(bases, cli, &mut run) {
+        Ok(expected) => expected,
+        Err(err) => {
+            eprintln!("Failed to compare <BASE> with <TARGET>: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut found = Vec::new();
+    mirror::compare(&expected, &is_linked, &mut found);
+
+    if cli.target.is_dir() {
+        let ignore: Vec<&Path> = cli.backup_dir().into_iter().chain(cli.provenance_dir()).collect();
+        match orphans::find(&cli.target, &expected.paths(), &ignore) {
+            Ok(orphans) => found.extend(
+                orphans
+                    .into_iter()
+                    .filter(|orphan| !(cli.write_provenance() && is_sidecar(orphan)))
+                    .map(|dest| mirror::Divergence {
+                        kind: mirror::Kind::Orphaned,
+                        source: None,
+                        dest,
+                    }),
+            ),
             Err(err) => {
-                eprintln!("Failed to create file: {err}");
-                continue;
+                eprintln!("Failed to look for orphans: {err}");
+                return ExitCode::FAILURE;
             }
         }
     }
 
-    ShouldExit::No
+    print!("{}", mirror::render(&mut found, cli.report_format(), &|path| cli.show(path).to_string()));
+    if found.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_DIVERGED)
+    }
+}
+
+/// Lists how the links a run would create differ from the ones recorded in `manifest` for `--diff-against`, without
+/// changing anything.
+fn diff_plan(bases: &[PathBuf], manifest: &Path, cli: &Cli) -> ExitCode {
+    let recorded = match Index::load(manifest) {
+        Ok(recorded) => recorded,
+        Err(err) => {
+            eprintln!("Failed to load `{}`: {err}", cli.show(manifest));
+            return ExitCode::from(EXIT_SETUP_FAILED);
+        }
+    };
+    let mut run = Run::default();
+    if let Err(err) = load_names(cli, &mut run) {
+        eprintln!("{err}");
+        return ExitCode::from(EXIT_SETUP_FAILED);
+    }
+    let expected = match expected_target(bases, cli, &mut run) {
+        Ok(expected) => expected,
+        Err(err) => {
+            eprintln!("Failed to work out what would be linked: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let links = plan::diff(&expected, recorded.links());
+    print!("{}", plan::render(&links, cli.report_format(), &|path| cli.show(path).to_string()));
+    if plan::changes(&links) {
+        ExitCode::from(EXIT_DIVERGED)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Lists what the run created for `--confirm-at-end` and removes it all again unless the user wants to keep it.
+fn review(cli: &Cli, run: &mut Run) -> io::Result<()> {
+    if run.created.is_empty() && run.displaced.is_empty() {
+        return Ok(());
+    }
+
+    println!("Created:");
+    for path in &run.created {
+        println!("  {}", cli.show(path));
+    }
+    if !run.displaced.is_empty() {
+        println!("Replaced:");
+        for displaced in &run.displaced {
+            println!("  {}", cli.show(&displaced.dest));
+        }
+    }
+    let keep = Confirm::new()
+        .with_prompt(format!("Keep these {} entries?", run.created.len()))
+        .default(true)
+        .interact_opt()
+        .map_err(|Error::IO(err)| err)?;
+    if keep != Some(false) {
+        return Ok(());
+    }
+
+    // Newest first, so directories are empty again by the time they're removed.
+    for path in run.created.iter().rev() {
+        let is_dir = path.symlink_metadata().is_ok_and(|metadata| metadata.is_dir());
+        let removed = if is_dir { fs::remove_dir(path) } else { fs::remove_file(path) };
+        if let Err(err) = removed {
+            eprintln!("Failed to remove `{}`: {err}", cli.show(path));
+        }
+    }
+    // Put back what the links replaced, now that they're out of the way.
+    for displaced in run.displaced.drain(..).rev() {
+        if let Err(err) = fs::rename(&displaced.moved_to, &displaced.dest) {
+            eprintln!(
+                "Failed to move `{}` back to `{}`: {err}",
+                cli.show(&displaced.moved_to),
+                cli.show(&displaced.dest)
+            );
+        }
+    }
+    println!("Removed everything the run created");
+
+    // The index would otherwise claim the removed links are current.
+    run.index = None;
+    run.summary.links_created = 0;
+    run.summary.dirs_created = 0;
+    Ok(())
+}
+
+/// Deletes the destinations `--confirm-at-end` kept aside once the run is kept after all, or was stopped before the
+/// question. Backups stay.
+fn discard_displaced(cli: &Cli, run: &mut Run) {
+    for displaced in run.displaced.drain(..).filter(|displaced| displaced.discard) {
+        let path = &displaced.moved_to;
+        if let Err(err) = fs::remove_file(path).or_else(|_| fs::remove_dir(path)) {
+            eprintln!("Failed to remove the replaced `{}`: {err}", cli.show(path));
+        }
+    }
+}
+
+/// Handles orphans and writes the files a run produces. Returns whether all of that worked.
+fn wrap_up(bases: &[PathBuf], cli: &Cli, run: &mut Run) -> bool {
+    let mut success = true;
+
+    // Orphans only make sense when mirroring directories.
+    if cli.on_orphan() != OnOrphan::Ignore && bases.iter().all(|base| base.is_dir()) {
+        if let Err(err) = handle_orphans(bases, cli, run) {
+            eprintln!("Failed to handle orphans: {err}");
+            success = false;
+        }
+    }
+
+    if let (Some(index_path), Some(index)) = (cli.index(), &run.index) {
+        if let Err(err) = index.save(index_path) {
+            eprintln!("Failed to save index `{}`: {err}", index_path.display());
+            success = false;
+        }
+    }
+
+    if let Some(remember_answers) = cli.remember_answers() {
+        if let Err(err) = answers::save(remember_answers, &run.remembered) {
+            eprintln!("Failed to save answers `{}`: {err}", remember_answers.display());
+            success = false;
+        }
+    }
+
+    if !run.unstable.is_empty() {
+        eprintln!("Still being written, run again later to link them:");
+        for source in &run.unstable {
+            eprintln!("  `{}`", cli.show(source));
+        }
+    }
+
+    if let Some(failures_file) = cli.failures_file() {
+        let retry = [run.failures.as_slice(), run.unstable.as_slice()].concat();
+        if let Err(err) = write_failures(failures_file, &retry, cli.failures_nul()) {
+            eprintln!("Failed to write failures to `{}`: {err}", failures_file.display());
+            success = false;
+        }
+    }
+
+    success
 }
 
+/// Exit code when the run couldn't start, because of invalid arguments or a failed check. Matches what clap exits
+/// with on invalid arguments.
+const EXIT_SETUP_FAILED: u8 = 2;
+/// Exit code when `--audit` found entries of <TARGET> out of sync with <BASE>, or `--diff-against` found links that
+/// changed.
+const EXIT_DIVERGED: u8 = 3;
+/// Exit code when `--timeout-total` ran out.
+const EXIT_TIMED_OUT: u8 = 124;
+/// Exit code when the run was stopped early, by cancelling a prompt or by `--filter-script`.
+const EXIT_ABORTED: u8 = 130;
+
 fn main() -> ExitCode {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
 
     if let Some(gen) = cli.completions() {
         generate(gen, &mut Cli::command(), Cli::command().get_name().to_string(), &mut io::stdout());
         return ExitCode::SUCCESS;
     }
 
-    if cli.base.is_file() {
-        let base_file_name = cli
-            .base
-            .file_name()
-            .expect("<BASE> was provided a file that doesn't have a valid filename by Rust rules");
-        let link = cli.target.join(base_file_name); // We have validated target to be a directory.
-        if let Err(err) = link_file(&cli.base, &link, &cli) {
-            eprintln!("Encountered and error while handling file: {err}");
-            return ExitCode::FAILURE;
-        }
+    if let Err(err) = cli.resolve_paths() {
+        eprintln!("{err}");
+        return ExitCode::from(EXIT_SETUP_FAILED);
+    }
 
+    if cli.dump_config() {
+        print!("{}", cli.resolved_config());
         return ExitCode::SUCCESS;
     }
 
-    let dirs = match cli.base.read_dir() {
-        Ok(dirs) => dirs,
+    let bases = match prepare(&mut cli) {
+        Ok(bases) => bases,
         Err(err) => {
-            eprintln!("Failed to read <BASE> dir: {err}");
-            return ExitCode::FAILURE;
+            eprintln!("{err}");
+            return ExitCode::from(EXIT_SETUP_FAILED);
         }
     };
 
-    recurse_into_dir(dirs, &cli.target, &cli);
+    if cli.count_only() {
+        return count_only(&bases, &cli);
+    }
+    if cli.audit() {
+        return audit_mirror(&bases, &cli);
+    }
+    if let Some(manifest) = cli.diff_against() {
+        return diff_plan(&bases, manifest, &cli);
+    }
 
-    ExitCode::SUCCESS
+    if let Some(pre_hook) = cli.pre_hook() {
+        if let Err(err) = pre_hook.run(iter::empty::<(&str, &str)>()) {
+            eprintln!("Pre-hook {err}, aborting");
+            return ExitCode::from(EXIT_SETUP_FAILED);
+        }
+    }
+
+    let mut run = match start_run(&cli) {
+        Ok(run) => run,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::from(EXIT_SETUP_FAILED);
+        }
+    };
+
+    let atomic_target = match stage_target(&mut cli, &mut run) {
+        Ok(atomic_target) => atomic_target,
+        Err(err) => {
+            eprintln!("Failed to create staging directory: {err}");
+            return ExitCode::from(EXIT_SETUP_FAILED);
+        }
+    };
+
+    let mut exit_code = ExitCode::SUCCESS;
+    let mut aborted = false;
+
+    for base in &bases {
+        if run.out_of_time() {
+            break;
+        }
+
+        match link_base(base, &cli, &mut run) {
+            Ok(ShouldExit::No) => {}
+            Ok(ShouldExit::Yes) => {
+                aborted = true;
+                break;
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                run.fail(base);
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+    }
+
+    touch_written_dirs(&cli, &mut run);
+
+    if let Some(target) = atomic_target {
+        let staging = mem::replace(&mut cli.target, target);
+        let complete = !aborted && !run.timed_out && run.summary.failed == 0;
+        if let Err(err) = finish_atomic_run(&staging, &cli.target, complete) {
+            eprintln!("{err}");
+            exit_code = ExitCode::FAILURE;
+        }
+    }
+
+    if cli.confirm_at_end() {
+        if !aborted {
+            if let Err(err) = review(&cli, &mut run) {
+                eprintln!("Error in prompt: {err}");
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+        discard_displaced(&cli, &mut run);
+    }
+
+    if !wrap_up(&bases, &cli, &mut run) {
+        exit_code = ExitCode::FAILURE;
+    }
+
+    print!("{}", run.summary.render(cli.report_format()));
+
+    if let Some(post_hook) = cli.post_hook() {
+        if let Err(err) = post_hook.run(run.summary.env_vars()) {
+            eprintln!("Post-hook {err}");
+            exit_code = ExitCode::FAILURE;
+        }
+    }
+
+    final_exit_code(&run, aborted, exit_code)
+}
+
+/// Picks the exit code of a finished run, the most severe outcome wins. `exit_code` covers failures outside linking.
+fn final_exit_code(run: &Run, aborted: bool, exit_code: ExitCode) -> ExitCode {
+    if run.timed_out {
+        return ExitCode::from(EXIT_TIMED_OUT);
+    }
+    if run.failed_fatally || run.summary.failed > 0 {
+        return ExitCode::FAILURE;
+    }
+    if aborted {
+        return ExitCode::from(EXIT_ABORTED);
+    }
+
+    exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, process,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    type Recorded = Arc<Mutex<Vec<(LinkStrategy, PathBuf, PathBuf)>>>;
+
+    /// Records what would have been linked instead of touching the filesystem.
+    #[derive(Debug, Default)]
+    struct Recorder {
+        links: Recorded,
+        fail: bool,
+    }
+
+    impl link::Linker for Recorder {
+        fn link(&self, strategy: LinkStrategy, original: &Path, link: &Path) -> io::Result<()> {
+            self.links
+                .lock()
+                .unwrap()
+                .push((strategy, original.to_path_buf(), link.to_path_buf()));
+            if self.fail {
+                Err(io::Error::other("refused by the test"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// Creates `<BASE>` with `files` and an empty <TARGET> in a fresh directory named after `test`.
+    fn scratch(test: &str, files: &[&str]) -> (PathBuf, PathBuf) {
+        let root = env::temp_dir().join(format!("tlu-{test}-{}", process::id()));
+        let (base, target) = (root.join("base"), root.join("target"));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&base).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        for file in files {
+            fs::write(base.join(file), file).unwrap();
+        }
+        (base, target)
+    }
+
+    fn cli(args: &[&str], base: &Path, target: &Path) -> Cli {
+        let paths = [base.to_str().unwrap(), target.to_str().unwrap()];
+        let mut cli = Cli::try_parse_from(["tlu"].iter().chain(args).chain(&paths)).unwrap();
+        cli.resolve_paths().unwrap();
+        cli
+    }
+
+    fn recording_run(base: &Path, fail: bool) -> (Run, Recorded) {
+        let recorder = Recorder {
+            fail,
+            ..Recorder::default()
+        };
+        let links = Arc::clone(&recorder.links);
+        let mut run = Run::default();
+        run.base = base.to_path_buf();
+        run.linker = Some(Arc::new(recorder));
+        (run, links)
+    }
+
+    #[test]
+    fn link_file_goes_through_the_linker() {
+        let (base, target) = scratch("linker-dispatch", &["a.txt"]);
+        let cli = cli(&["-f", "--link-strategy", "copy"], &base, &target);
+        let (mut run, links) = recording_run(&base, false);
+
+        let link = target.join("a.txt");
+        link_file(&base.join("a.txt"), &link, cli.link_strategy(), &cli, &mut run).unwrap();
+
+        assert_eq!(*links.lock().unwrap(), vec![(LinkStrategy::Copy, base.join("a.txt"), link.clone())]);
+        assert_eq!(run.summary.links_created, 1);
+        // The fake never created anything.
+        assert!(link.symlink_metadata().is_err());
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn walk_picks_the_strategy_for_every_file() {
+        let (base, target) = scratch("linker-walk", &["a.txt", "b.md"]);
+        let cli = cli(&["-f", "--copy-matching", "*.txt"], &base, &target);
+        let (mut run, links) = recording_run(&base, false);
+
+        walk_dir(&base, &target, &cli, &mut run).unwrap();
+
+        let mut links = links.lock().unwrap().clone();
+        links.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            links,
+            vec![
+                (LinkStrategy::Copy, base.join("a.txt"), target.join("a.txt")),
+                (LinkStrategy::Hard, base.join("b.md"), target.join("b.md")),
+            ]
+        );
+        assert_eq!(run.summary.links_created, 2);
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn failed_links_are_counted() {
+        let (base, target) = scratch("linker-failure", &["a.txt"]);
+        let cli = cli(&["-f"], &base, &target);
+        let (mut run, links) = recording_run(&base, true);
+
+        walk_dir(&base, &target, &cli, &mut run).unwrap();
+
+        assert_eq!(links.lock().unwrap().len(), 1);
+        assert_eq!(run.summary.links_created, 0);
+        assert_eq!(run.summary.failed, 1);
+        assert_eq!(run.failures, vec![base.join("a.txt")]);
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    /// Destinations `expected_target` works out for linking `base` with `cli`, sorted.
+    fn expected_dests(base: &Path, cli: &Cli) -> Vec<PathBuf> {
+        let mut run = Run::default();
+        load_names(cli, &mut run).unwrap();
+        let expected = expected_target(&[base.to_path_buf()], cli, &mut run).unwrap();
+        let mut dests: Vec<PathBuf> = expected.entries.into_iter().map(|entry| entry.dest).collect();
+        dests.sort();
+        dests
+    }
+
+    #[test]
+    fn orphans_expect_rewritten_extensions() {
+        let (base, target) = scratch("orphans-rewrite-ext", &["doc.markdown", "notes.txt"]);
+        let cli = cli(&["-f", "-s", "--rewrite-ext", "markdown=md", "--on-orphan", "remove"], &base, &target);
+        let mut run = Run::default();
+        run.base = base.clone();
+
+        walk_dir(&base, &target, &cli, &mut run).unwrap();
+        fs::write(target.join("doc.markdown"), "stale").unwrap();
+        let expected = expected_target(std::slice::from_ref(&base), &cli, &mut run).unwrap();
+
+        assert_eq!(expected_dests(&base, &cli), vec![target.join("doc.md"), target.join("notes.txt")]);
+        let orphans = orphans::find(&target, &expected.paths(), &[]).unwrap();
+        assert_eq!(orphans, vec![target.join("doc.markdown")]);
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn orphans_expect_mapped_names() {
+        let (base, target) = scratch("orphans-name-map", &["a.txt", "b.txt"]);
+        let name_map = base.parent().unwrap().join("names");
+        fs::write(&name_map, "a.txt\tRENAMED.txt\n").unwrap();
+        let cli = cli(&["-f", "--name-map", name_map.to_str().unwrap()], &base, &target);
+
+        assert_eq!(expected_dests(&base, &cli), vec![target.join("RENAMED.txt"), target.join("b.txt")]);
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn audit_follows_layout_and_renames() {
+        let (base, target) = scratch("audit-layout", &["doc.markdown", "notes.txt"]);
+        fs::create_dir(base.join("sub")).unwrap();
+        fs::write(base.join("sub").join("deep.txt"), "deep").unwrap();
+        let args = ["-f", "--layout", "by-ext", "--rewrite-ext", "markdown=md", "--dir-template", "{upper}"];
+        let cli = cli(&args, &base, &target);
+        let mut run = Run::default();
+        run.base = base.clone();
+
+        walk_dir(&base, &target, &cli, &mut run).unwrap();
+        let expected = expected_target(std::slice::from_ref(&base), &cli, &mut run).unwrap();
+        let is_linked = |source: &Path, dest: &Path, _: &Path| index::same_file(dest, source);
+        let mut found = Vec::new();
+        mirror::compare(&expected, &is_linked, &mut found);
+
+        assert!(found.is_empty(), "{found:?}");
+        assert!(target.join("MARKDOWN").join("doc.md").is_file());
+        assert_eq!(orphans::find(&target, &expected.paths(), &[]).unwrap(), Vec::<PathBuf>::new());
+
+        fs::remove_file(target.join("TXT").join("deep.txt")).unwrap();
+        mirror::compare(&expected, &is_linked, &mut found);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].kind, &found[0].dest), (mirror::Kind::Missing, &target.join("TXT").join("deep.txt")));
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn confirm_at_end_keeps_replaced_destinations_aside() {
+        let (base, target) = scratch("confirm-displaced", &["a.txt"]);
+        fs::write(target.join("a.txt"), "precious").unwrap();
+        let cli = cli(&["-f", "--on-conflict", "overwrite", "--confirm-at-end"], &base, &target);
+        let mut run = Run::default();
+        run.base = base.clone();
+
+        walk_dir(&base, &target, &cli, &mut run).unwrap();
+
+        let moved_to = target.join("a.txt.tlu-displaced");
+        assert_eq!(fs::read_to_string(&moved_to).unwrap(), "precious");
+        assert_eq!(run.displaced.len(), 1);
+        assert_eq!(run.displaced[0].dest, target.join("a.txt"));
+        discard_displaced(&cli, &mut run);
+        assert!(moved_to.symlink_metadata().is_err());
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn deep_relative_symlinks_fall_back_to_absolute() {
+        let (base, target) = scratch("relative-updepth", &["a.txt"]);
+        let deep = target.join("one").join("two");
+        fs::create_dir_all(&deep).unwrap();
+        let cli = cli(&["-s", "--symlink-policy", "relative", "--relative-max-updepth", "2"], &base, &target);
+        let source = fs::canonicalize(base.join("a.txt")).unwrap();
+
+        assert_eq!(symlink_source(&source, &target.join("a.txt"), &cli).unwrap(), Path::new("../base/a.txt"));
+        assert_eq!(symlink_source(&source, &deep.join("a.txt"), &cli).unwrap(), source);
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn parallel_workers_add_up_to_the_run() {
+        let (base, target) = scratch("parallel-dirs", &["top.txt"]);
+        for dir in ["a", "b", "c", "d"] {
+            fs::create_dir(base.join(dir)).unwrap();
+            for file in ["1", "2", "3"] {
+                fs::write(base.join(dir).join(file), file).unwrap();
+            }
+        }
+        let cli = cli(&["-f", "--parallel-dirs", "3"], &base, &target);
+        let (mut run, links) = recording_run(&base, true);
+        run.workers = Some(Arc::new(AtomicUsize::new(2)));
+
+        walk_dir(&base, &target, &cli, &mut run).unwrap();
+
+        assert_eq!(links.lock().unwrap().len(), 13);
+        assert_eq!((run.summary.dirs_created, run.summary.failed), (4, 13));
+        let mut failures = run.failures.clone();
+        failures.sort();
+        assert_eq!(failures.len(), 13);
+        assert_eq!(failures[0], base.join("a").join("1"));
+        // Every worker went back to being spare.
+        assert_eq!(run.workers.unwrap().load(std::sync::atomic::Ordering::SeqCst), 2);
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn workers_share_max_open_dirs() {
+        let run = Run::default();
+        let worker = run.fork();
+
+        assert!(worker.claim_open_dir(Some(3)));
+        assert!(run.claim_open_dir(Some(3)));
+        // Two handles are open between them, a third would reach the limit.
+        assert!(!worker.claim_open_dir(Some(3)));
+        worker.release_open_dir();
+        assert!(run.claim_open_dir(Some(3)));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn auto_confirm_counts_past_dangling_symlinks() {
+        let (base, target) = scratch("auto-confirm-dangling", &["a.txt"]);
+        std::os::unix::fs::symlink(base.join("nowhere"), base.join("dangling")).unwrap();
+        let mut cli = cli(&["--auto-confirm-under", "10"], &base, &target);
+
+        prepare(&mut cli).unwrap();
+
+        assert!(cli.assume_yes());
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn index_reruns_reuse_directories() {
+        let (base, target) = scratch("index-rerun", &["a.txt"]);
+        fs::create_dir_all(base.join("b").join("sub")).unwrap();
+        fs::write(base.join("b").join("sub").join("c.txt"), "c").unwrap();
+        let index = base.parent().unwrap().join("index");
+        let cli = cli(&["-f", "--index", index.to_str().unwrap()], &base, &target);
+
+        for links in [2, 0] {
+            let mut run = start_run(&cli).unwrap();
+            assert!(!link_base(&base, &cli, &mut run).unwrap().should_exit());
+            assert!(wrap_up(std::slice::from_ref(&base), &cli, &mut run));
+            assert_eq!((run.summary.links_created, run.summary.failed), (links, 0));
+            assert_eq!(final_exit_code(&run, false, ExitCode::SUCCESS), ExitCode::SUCCESS);
+        }
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn preserve_permissions_copies_the_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (base, target) = scratch("preserve-permissions", &["a.sh"]);
+        let cli = cli(&["-f", "--preserve-permissions"], &base, &target);
+        let (original, copy) = (base.join("a.sh"), target.join("a.sh"));
+        fs::set_permissions(&original, fs::Permissions::from_mode(0o750)).unwrap();
+        fs::write(&copy, "a.sh").unwrap();
+        fs::set_permissions(&copy, fs::Permissions::from_mode(0o600)).unwrap();
+
+        preserve_metadata(&original, &copy, &cli);
+        assert_eq!(fs::metadata(&copy).unwrap().permissions().mode() & 0o777, 0o750);
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn flatten_naming_tells_same_named_files_apart() {
+        for (naming, renamed) in [("number", "track.mp3.1"), ("parent", "_track.mp3"), ("hash", "track~")] {
+            let (base, target) = scratch("flatten-naming", &[]);
+            for dir in ["music", "other"] {
+                fs::create_dir(base.join(dir)).unwrap();
+                fs::write(base.join(dir).join("track.mp3"), dir).unwrap();
+            }
+            let args = ["-f", "--layout", "flat", "--flatten-naming", naming, "--on-conflict", "overwrite"];
+            let cli = cli(&args, &base, &target);
+
+            // Running again replaces the same links instead of numbering the ones of the first run.
+            for _ in 0..2 {
+                let mut run = Run::default();
+                run.base = base.clone();
+                walk_dir(&base, &target, &cli, &mut run).unwrap();
+                assert_eq!(run.summary.failed, 0);
+            }
+
+            let mut names: Vec<String> = fs::read_dir(&target)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            assert_eq!(names.len(), 2, "{naming}: {names:?}");
+            assert!(names.contains(&"track.mp3".to_string()), "{naming}: {names:?}");
+            assert!(names.iter().any(|name| name != "track.mp3" && name.contains(renamed)), "{naming}: {names:?}");
+            let mut dests: Vec<PathBuf> = names.iter().map(|name| target.join(name)).collect();
+            dests.sort();
+            assert_eq!(expected_dests(&base, &cli), dests);
+            fs::remove_dir_all(base.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn diff_against_compares_with_the_index() {
+        let (base, target) = scratch("diff-against", &["kept.txt", "gone.txt"]);
+        let index = base.parent().unwrap().join("index");
+        let cli = cli(&["-f", "--index", index.to_str().unwrap()], &base, &target);
+        let mut run = start_run(&cli).unwrap();
+        walk_dir(&base, &target, &cli, &mut run).unwrap();
+        run.index.unwrap().save(&index).unwrap();
+
+        fs::remove_file(base.join("gone.txt")).unwrap();
+        fs::write(base.join("new.txt"), "new").unwrap();
+        let mut run = Run::default();
+        let expected = expected_target(std::slice::from_ref(&base), &cli, &mut run).unwrap();
+        let links = plan::diff(&expected, Index::load(&index).unwrap().links());
+
+        let changes: Vec<(plan::Change, &str)> = links
+            .iter()
+            .map(|link| (link.change, link.dest.file_name().unwrap().to_str().unwrap()))
+            .collect();
+        let expected_changes = [
+            (plan::Change::Added, "new.txt"),
+            (plan::Change::Removed, "gone.txt"),
+            (plan::Change::Unchanged, "kept.txt"),
+        ];
+        assert_eq!(changes, expected_changes);
+        assert!(plan::changes(&links));
+        fs::remove_dir_all(base.parent().unwrap()).unwrap();
+    }
 }
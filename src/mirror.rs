@@ -0,0 +1,272 @@
+//! Working out what <TARGET> should contain, and comparing it with what it does for `--audit`.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    ffi::{OsStr, OsString},
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    layout::{FlattenNaming, Layout},
+    summary::{self, ReportFormat},
+};
+
+/// How an entry of <TARGET> differs from <BASE>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    /// A source has no link.
+    Missing,
+    /// Something is where a link should be, but doesn't link to the source.
+    Mismatched,
+    /// An entry of <TARGET> has no source.
+    Orphaned,
+}
+
+impl Kind {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::Mismatched => "mismatched",
+            Self::Orphaned => "orphaned",
+        }
+    }
+}
+
+/// One entry out of sync.
+#[derive(Debug)]
+pub struct Divergence {
+    pub kind: Kind,
+    /// Source of the link, `None` for orphans.
+    pub source: Option<PathBuf>,
+    pub dest: PathBuf,
+}
+
+/// How the walk names and places what it links, so what <TARGET> is expected to contain matches what a run creates.
+pub struct Naming<'a> {
+    pub layout: Layout,
+    pub flatten: FlattenNaming,
+    /// Directories are linked as a whole instead of recreated.
+    pub link_dirs: bool,
+    /// Name of the link to a source that would be created at the given path.
+    pub file_name: &'a dyn Fn(&Path, &Path) -> OsString,
+    /// Name a directory of <BASE> is recreated under, or a `--layout` folder is created under.
+    pub dir_name: &'a dyn Fn(&OsStr) -> OsString,
+}
+
+/// An entry <TARGET> should have.
+#[derive(Debug)]
+pub struct Entry {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    /// Path of the source relative to its base.
+    pub relative: PathBuf,
+    /// Whether the entry is a recreated directory rather than a link.
+    pub recreated: bool,
+}
+
+/// What <TARGET> should contain once every base is linked into it.
+#[derive(Debug, Default)]
+pub struct Expected {
+    /// Links and recreated directories, every directory before its contents.
+    pub entries: Vec<Entry>,
+    /// `--layout` folders, which have no source of their own.
+    pub folders: BTreeSet<PathBuf>,
+    /// Links expected in `--layout` folders, to tell apart names that collide like the walk does.
+    placed: HashSet<PathBuf>,
+}
+
+impl Expected {
+    /// Every path of <TARGET> that's expected to exist.
+    pub fn paths(&self) -> HashSet<&Path> {
+        let dests = self.entries.iter().map(|entry| entry.dest.as_path());
+        dests.chain(self.folders.iter().map(PathBuf::as_path)).collect()
+    }
+}
+
+/// Adds what linking `base` into `target` creates to `expected`, or what linking it at `target` itself does for a
+/// single file.
+pub fn expect(base: &Path, target: &Path, naming: &Naming, expected: &mut Expected) -> io::Result<()> {
+    if base.is_file() {
+        expected.entries.push(Entry {
+            source: base.to_path_buf(),
+            dest: target.with_file_name((naming.file_name)(base, target)),
+            relative: PathBuf::from(base.file_name().unwrap_or_default()),
+            recreated: false,
+        });
+        return Ok(());
+    }
+
+    expect_dir(base, target, target, Path::new(""), naming, expected)
+}
+
+fn expect_dir(
+    dir: &Path,
+    root: &Path,
+    target: &Path,
+    relative: &Path,
+    naming: &Naming,
+    expected: &mut Expected,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let (source, relative) = (entry.path(), relative.join(entry.file_name()));
+        let is_dir = source.is_dir();
+
+        if is_dir && !naming.link_dirs {
+            // Other layouts don't recreate directories, their files go into the layout's folders.
+            let dest = if naming.layout.mirrors() {
+                let dest = target.join((naming.dir_name)(&entry.file_name()));
+                expected.entries.push(Entry {
+                    source: source.clone(),
+                    dest: dest.clone(),
+                    relative: relative.clone(),
+                    recreated: true,
+                });
+                dest
+            } else {
+                target.to_path_buf()
+            };
+            expect_dir(&source, root, &dest, &relative, naming, expected)?;
+            continue;
+        }
+
+        let dir = if naming.layout.mirrors() || is_dir {
+            target.to_path_buf()
+        } else {
+            // The walk can't place sources whose metadata can't be read either.
+            let Ok(metadata) = fs::metadata(&source) else {
+                continue;
+            };
+            let folder = naming.layout.dest_dir(&relative, &metadata);
+            if folder.as_os_str().is_empty() {
+                root.to_path_buf()
+            } else {
+                let folder = root.join((naming.dir_name)(folder.as_os_str()));
+                expected.folders.insert(folder.clone());
+                folder
+            }
+        };
+        let link = dir.join(entry.file_name());
+        let mut dest = link.with_file_name((naming.file_name)(&source, &link));
+        if !naming.layout.mirrors() {
+            dest = naming.flatten.disambiguate(dest, &relative, &|dest| expected.placed.contains(dest));
+            expected.placed.insert(dest.clone());
+        }
+        expected.entries.push(Entry {
+            dest,
+            source,
+            relative,
+            recreated: false,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that every entry in `expected` exists. `is_linked(source, dest, relative)` decides whether an existing
+/// destination links to its source.
+pub fn compare(expected: &Expected, is_linked: &dyn Fn(&Path, &Path, &Path) -> bool, found: &mut Vec<Divergence>) {
+    let mut absent: Vec<&Path> = Vec::new();
+    for entry in &expected.entries {
+        // Everything below a missing directory is missing as well, only the directory is reported.
+        if absent.iter().any(|dir| entry.dest.starts_with(dir)) {
+            continue;
+        }
+
+        let in_sync = if entry.recreated {
+            entry.dest.is_dir()
+        } else {
+            is_linked(&entry.source, &entry.dest, &entry.relative)
+        };
+        let kind = if entry.dest.symlink_metadata().is_err() {
+            Kind::Missing
+        } else if !in_sync {
+            Kind::Mismatched
+        } else {
+            continue;
+        };
+
+        if entry.recreated {
+            absent.push(&entry.dest);
+        }
+        found.push(Divergence {
+            kind,
+            source: Some(entry.source.clone()),
+            dest: entry.dest.clone(),
+        });
+    }
+}
+
+/// Renders `found`, grouped by kind and sorted by destination. `show` formats paths for text output.
+pub fn render(found: &mut [Divergence], format: ReportFormat, show: &dyn Fn(&Path) -> String) -> String {
+    found.sort_by(|a, b| (a.kind, &a.dest).cmp(&(b.kind, &b.dest)));
+    let mut out = String::new();
+
+    match format {
+        ReportFormat::Text => {
+            let mut kind = None;
+            for divergence in found.iter() {
+                if kind != Some(divergence.kind) {
+                    kind = Some(divergence.kind);
+                    writeln!(out, "{}:", text_heading(divergence.kind)).unwrap();
+                }
+                match &divergence.source {
+                    Some(source) => writeln!(out, "  `{}` for `{}`", show(&divergence.dest), show(source)).unwrap(),
+                    None => writeln!(out, "  `{}`", show(&divergence.dest)).unwrap(),
+                }
+            }
+            if found.is_empty() {
+                writeln!(out, "<TARGET> mirrors <BASE>").unwrap();
+            }
+        }
+        ReportFormat::Json => {
+            let groups: Vec<String> = [Kind::Missing, Kind::Mismatched, Kind::Orphaned]
+                .iter()
+                .map(|&kind| {
+                    let entries: Vec<String> = found
+                        .iter()
+                        .filter(|divergence| divergence.kind == kind)
+                        .map(|divergence| {
+                            let source = divergence.source.as_ref().map_or_else(
+                                || "null".to_string(),
+                                |source| format!("\"{}\"", summary::json_escape(&source.to_string_lossy())),
+                            );
+                            format!(
+                                "{{\"source\":{source},\"dest\":\"{}\"}}",
+                                summary::json_escape(&divergence.dest.to_string_lossy())
+                            )
+                        })
+                        .collect();
+                    format!("\"{}\":[{}]", kind.name(), entries.join(","))
+                })
+                .collect();
+            writeln!(out, "{{{}}}", groups.join(",")).unwrap();
+        }
+        ReportFormat::Csv => {
+            writeln!(out, "kind,source,dest").unwrap();
+            for divergence in found.iter() {
+                let source = divergence.source.as_ref().map(|source| source.to_string_lossy()).unwrap_or_default();
+                writeln!(
+                    out,
+                    "{},{},{}",
+                    divergence.kind.name(),
+                    summary::csv_escape(&source),
+                    summary::csv_escape(&divergence.dest.to_string_lossy())
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+const fn text_heading(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Missing => "Missing links",
+        Kind::Mismatched => "Not linking to their source",
+        Kind::Orphaned => "Without a source",
+    }
+}
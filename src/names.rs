@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io::{self, Read},
+    path::{self, Path, PathBuf},
+};
+
+/// How much of a file `frontmatter_value` looks at.
+const FRONTMATTER_LIMIT: u64 = 4096;
+
+/// Characters Windows, and the FAT and SMB filesystems shaped by it, don't allow in names.
+const RESTRICTED: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Checks that `name` can be used as a single path component, used to validate interactively entered names.
+pub fn validate(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Name can't be empty".to_string());
+    }
+    if name == "." || name == ".." {
+        return Err(format!("`{name}` isn't a valid name"));
+    }
+    if name.chars().any(path::is_separator) {
+        return Err("Name can't contain path separators".to_string());
+    }
+
+    #[cfg(target_family = "windows")]
+    {
+        const RESERVED: [&str; 22] = [
+            "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+            "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+        ];
+
+        // Reserved names stay reserved with any extension, `NUL.txt` is still `NUL`.
+        let stem = name.split('.').next().unwrap_or(name).trim_end();
+        if RESERVED.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+            return Err(format!("`{name}` is a reserved name on Windows"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills `template` in for `name`. `{name}` is replaced by the name as is, `{lower}` and `{upper}` by the name in
+/// lower or upper case. Fails on unknown placeholders.
+pub fn render_template(template: &str, name: &str) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| "unclosed `{`".to_string())?
+            + start;
+
+        match &rest[start + 1..end] {
+            "name" => rendered.push_str(name),
+            "lower" => rendered.push_str(&name.to_lowercase()),
+            "upper" => rendered.push_str(&name.to_uppercase()),
+            placeholder => {
+                return Err(format!(
+                    "unknown placeholder `{{{placeholder}}}`, expected `{{name}}`, `{{lower}}` or `{{upper}}`"
+                ))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Applies the first of `--rewrite-ext`'s `(from, to)` pairs matching the final extension of `name`. Returns `None`
+/// if none match.
+pub fn rewrite_extension(name: &OsStr, rewrites: &[(String, String)]) -> Option<OsString> {
+    let extension = Path::new(name).extension()?;
+    let (_, to) = rewrites.iter().find(|(from, _)| extension == from.as_str())?;
+
+    Some(Path::new(name).with_extension(to).into_os_string())
+}
+
+/// Reads `key` from the YAML frontmatter at the start of `file`, for `--name-from-frontmatter`.
+///
+/// Only simple `key: value` lines in the first few KiB are understood. Returns `None` if the file doesn't start with
+/// frontmatter or the key is missing.
+pub fn frontmatter_value(file: &Path, key: &str) -> io::Result<Option<String>> {
+    let mut start = Vec::new();
+    File::open(file)?.take(FRONTMATTER_LIMIT).read_to_end(&mut start)?;
+    // A cut off multibyte character at the end doesn't matter, frontmatter has to be closed well before that.
+    let start = String::from_utf8_lossy(&start);
+
+    let mut lines = start.lines();
+    if lines.next().map(str::trim_end) != Some("---") {
+        return Ok(None);
+    }
+
+    for line in lines.take_while(|line| line.trim_end() != "---") {
+        let Some((line_key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if line_key.trim() == key {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')))
+                .unwrap_or(value);
+            return Ok(Some(value.to_string()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Turns `text` into a lower case name of letters, digits and dashes, e.g. `Hello, World!` into `hello-world`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Whether `c` can be used in names. Control characters never can, with `restricted` the ones Windows rejects can't
+/// either.
+pub fn is_allowed(c: char, restricted: bool) -> bool {
+    !c.is_control() && (!restricted || !RESTRICTED.contains(&c))
+}
+
+/// Replaces every character of `name` that isn't allowed with `replacement` for `--sanitize-names`.
+pub fn sanitize(name: &str, restricted: bool, replacement: char) -> String {
+    name.chars()
+        .map(|c| if is_allowed(c, restricted) { c } else { replacement })
+        .collect()
+}
+
+/// Shortens `name` to at most `max` bytes for `--max-name-length`, keeping its extension.
+///
+/// A hash of the full name is appended so that names sharing a long prefix don't collide. `max` has to leave room
+/// for the hash.
+pub fn truncate(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+
+    let suffix = format!("~{:08x}", fnv1a(name));
+    let (stem, extension) = match name.rfind('.') {
+        // A leading dot marks a hidden file rather than an extension.
+        Some(dot) if dot > 0 && name.len() - dot + suffix.len() < max => name.split_at(dot),
+        _ => (name, ""),
+    };
+
+    let mut end = max - suffix.len() - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{suffix}{extension}", &stem[..end])
+}
+
+/// 32 bit FNV-1a, stable across runs and Rust versions unlike the standard library's hasher.
+pub fn fnv1a(text: &str) -> u32 {
+    text.bytes()
+        .fold(0x811c_9dc5, |hash, byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193))
+}
+
+/// Loads a `--name-map` file of `source<TAB>name` lines, where `source` is relative to <BASE>. Empty lines are ignored.
+pub fn load_map(path: &Path) -> io::Result<HashMap<PathBuf, String>> {
+    let mut map = HashMap::new();
+
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let invalid = |reason: String| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {reason}", number + 1))
+        };
+        let (source, name) = line
+            .split_once('\t')
+            .ok_or_else(|| invalid("expected `source<TAB>name`".to_string()))?;
+        validate(name).map_err(invalid)?;
+
+        map.insert(PathBuf::from(source), name.to_string());
+    }
+
+    Ok(map)
+}
@@ -0,0 +1,64 @@
+//! Finding entries in <TARGET> that no longer have a source.
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Finds entries under `target` that aren't in `expected`, the paths linking the bases creates.
+///
+/// Orphaned directories are reported as a whole without descending into them. Symbolic links to directories
+/// aren't followed and paths in `ignore` are skipped entirely.
+pub fn find(target: &Path, expected: &HashSet<&Path>, ignore: &[&Path]) -> io::Result<Vec<PathBuf>> {
+    let mut orphans = Vec::new();
+    walk(target, expected, ignore, &mut orphans)?;
+    orphans.sort();
+    Ok(orphans)
+}
+
+fn walk(dir: &Path, expected: &HashSet<&Path>, ignore: &[&Path], orphans: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if ignore.contains(&path.as_path()) {
+            continue;
+        }
+
+        if !expected.contains(path.as_path()) {
+            orphans.push(path);
+        } else if entry.file_type()?.is_dir() {
+            walk(&path, expected, ignore, orphans)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether `path` is a link: a symbolic link or, on Unix, a file with more than one hard link.
+pub fn is_link(path: &Path) -> io::Result<bool> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        return Ok(true);
+    }
+
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if metadata.is_file() && metadata.nlink() > 1 {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Deletes an orphan, whether it's a link, file or directory. Symbolic links to directories aren't followed.
+pub fn remove(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
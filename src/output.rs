@@ -0,0 +1,51 @@
+//! Printing, which `--parallel-dirs` collects per subtree so it comes out in walk order.
+
+use std::cell::RefCell;
+
+/// Where a line goes.
+#[derive(Debug, Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// What was printed while capturing, in order.
+pub type Lines = Vec<(Stream, String)>;
+
+thread_local! {
+    /// Output of the captures running on this thread, innermost last.
+    static CAPTURES: RefCell<Vec<Lines>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Prints `text`, or adds it to the innermost capture of this thread if there is one.
+pub fn print(stream: Stream, text: String) {
+    let Some(text) = CAPTURES.with_borrow_mut(|captures| match captures.last_mut() {
+        Some(lines) => {
+            lines.push((stream, text));
+            None
+        }
+        None => Some(text),
+    }) else {
+        return;
+    };
+
+    match stream {
+        Stream::Stdout => std::print!("{text}"),
+        Stream::Stderr => std::eprint!("{text}"),
+    }
+}
+
+/// Runs `f`, collecting what it prints on this thread instead of printing it.
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Lines) {
+    CAPTURES.with_borrow_mut(|captures| captures.push(Lines::new()));
+    let result = f();
+    let lines = CAPTURES.with_borrow_mut(Vec::pop).unwrap_or_default();
+    (result, lines)
+}
+
+/// Prints what `capture` collected, into the enclosing capture if there is one.
+pub fn replay(lines: Lines) {
+    for (stream, text) in lines {
+        print(stream, text);
+    }
+}
@@ -0,0 +1,132 @@
+//! Comparing the links a run would create with the `--index` file of an earlier run, for `--diff-against`.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    mirror::Expected,
+    summary::{self, ReportFormat},
+};
+
+/// How a link of the plan differs from the earlier run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    /// The earlier run didn't create the link.
+    Added,
+    /// The earlier run created a link the plan doesn't have.
+    Removed,
+    /// Both have the link.
+    Unchanged,
+}
+
+impl Change {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Unchanged => "unchanged",
+        }
+    }
+
+    const fn heading(self) -> &'static str {
+        match self {
+            Self::Added => "Added links",
+            Self::Removed => "Removed links",
+            Self::Unchanged => "Unchanged links",
+        }
+    }
+}
+
+/// One link of the plan or of the earlier run.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Link {
+    pub change: Change,
+    pub source: PathBuf,
+    pub dest: PathBuf,
+}
+
+/// Compares the links in `expected` with the `(source, dest)` pairs the earlier run `recorded`, sorted by change and
+/// destination. A link that moves to another destination is removed at the old one and added at the new one.
+pub fn diff<'a>(expected: &Expected, recorded: impl Iterator<Item = (&'a Path, &'a Path)>) -> Vec<Link> {
+    let planned: BTreeSet<(&Path, &Path)> = expected
+        .entries
+        .iter()
+        .filter(|entry| !entry.recreated)
+        .map(|entry| (entry.dest.as_path(), entry.source.as_path()))
+        .collect();
+    let recorded: BTreeSet<(&Path, &Path)> = recorded.map(|(source, dest)| (dest, source)).collect();
+
+    let link = |change, (dest, source): &(&Path, &Path)| Link {
+        change,
+        source: source.to_path_buf(),
+        dest: dest.to_path_buf(),
+    };
+    let added = planned.difference(&recorded).map(|pair| link(Change::Added, pair));
+    let removed = recorded.difference(&planned).map(|pair| link(Change::Removed, pair));
+    let unchanged = planned.intersection(&recorded).map(|pair| link(Change::Unchanged, pair));
+    added.chain(removed).chain(unchanged).collect()
+}
+
+/// Whether running would change anything compared to the earlier run.
+pub fn changes(links: &[Link]) -> bool {
+    links.iter().any(|link| link.change != Change::Unchanged)
+}
+
+/// Renders `links` grouped by change. `show` formats paths for text output.
+pub fn render(links: &[Link], format: ReportFormat, show: &dyn Fn(&Path) -> String) -> String {
+    let mut out = String::new();
+
+    match format {
+        ReportFormat::Text => {
+            let mut change = None;
+            for link in links {
+                if change != Some(link.change) {
+                    change = Some(link.change);
+                    writeln!(out, "{}:", link.change.heading()).unwrap();
+                }
+                writeln!(out, "  `{}` for `{}`", show(&link.dest), show(&link.source)).unwrap();
+            }
+            if links.is_empty() {
+                writeln!(out, "Neither run links anything").unwrap();
+            }
+        }
+        ReportFormat::Json => {
+            let groups: Vec<String> = [Change::Added, Change::Removed, Change::Unchanged]
+                .iter()
+                .map(|&change| {
+                    let entries: Vec<String> = links
+                        .iter()
+                        .filter(|link| link.change == change)
+                        .map(|link| {
+                            format!(
+                                "{{\"source\":\"{}\",\"dest\":\"{}\"}}",
+                                summary::json_escape(&link.source.to_string_lossy()),
+                                summary::json_escape(&link.dest.to_string_lossy())
+                            )
+                        })
+                        .collect();
+                    format!("\"{}\":[{}]", change.name(), entries.join(","))
+                })
+                .collect();
+            writeln!(out, "{{{}}}", groups.join(",")).unwrap();
+        }
+        ReportFormat::Csv => {
+            writeln!(out, "change,source,dest").unwrap();
+            for link in links {
+                writeln!(
+                    out,
+                    "{},{},{}",
+                    link.change.name(),
+                    summary::csv_escape(&link.source.to_string_lossy()),
+                    summary::csv_escape(&link.dest.to_string_lossy())
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    out
+}
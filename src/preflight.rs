@@ -0,0 +1,222 @@
+//! Checks run once before any linking starts.
+
+use std::{
+    env,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{device, glob};
+
+/// Checks that `dir` is owned by the user running this process.
+#[cfg(target_family = "unix")]
+pub fn check_owner(dir: &Path) -> Result<(), String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = fs::metadata(dir)
+        .map_err(|err| format!("Can't open `{}`: {err}", dir.display()))?
+        .uid();
+    // SAFETY: `geteuid` has no preconditions and always succeeds.
+    let uid = unsafe { libc::geteuid() };
+
+    if owner == uid {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{}` is owned by uid {owner}, not by the current user (uid {uid})",
+            dir.display()
+        ))
+    }
+}
+
+/// Checks that `dir` is owned by the user running this process.
+#[cfg(not(target_family = "unix"))]
+pub fn check_owner(_dir: &Path) -> Result<(), String> {
+    eprintln!("--require-owner is only supported on Unix, ignoring it");
+    Ok(())
+}
+
+/// Checks that files can be created in `dir` by creating and removing a temporary one.
+pub fn check_writable(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(format!(".tlu-write-check-{}", process::id()));
+    File::options()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(|err| format!("`{}` appears read-only: {err}", dir.display()))?;
+
+    fs::remove_file(&probe).map_err(|err| format!("Failed to remove `{}`: {err}", probe.display()))
+}
+
+/// Checks that every base is on the same filesystem as `target`, which hard links need.
+pub fn check_same_device(bases: &[PathBuf], target: &Path) -> Result<(), String> {
+    let target_device =
+        device::device_of(target).map_err(|err| format!("Failed to get the device of `{}`: {err}", target.display()))?;
+
+    for base in bases {
+        let base_device =
+            device::device_of(base).map_err(|err| format!("Failed to get the device of `{}`: {err}", base.display()))?;
+        if base_device != target_device {
+            return Err(format!(
+                "`{}` is on a different filesystem than `{}` and hard links can't cross filesystems. Use --symbolic or \
+                 --link-strategy copy instead",
+                base.display(),
+                target.display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Targets refused unless `--allow-dangerous-target` is given, matched against the canonical path of <TARGET>.
+#[cfg(target_family = "unix")]
+const DANGEROUS_TARGETS: [&str; 14] = [
+    "/", "/bin", "/boot", "/dev", "/etc", "/lib", "/lib64", "/proc", "/sbin", "/sys", "/usr", "/usr/bin", "/usr/lib",
+    "/var",
+];
+/// Targets refused unless `--allow-dangerous-target` is given, matched against the canonical path of <TARGET>.
+#[cfg(target_family = "windows")]
+const DANGEROUS_TARGETS: [&str; 5] = ["?:\\", "?:\\Windows", "?:\\Windows\\*", "?:\\Program Files", "?:\\Users"];
+
+#[cfg(target_family = "unix")]
+const HOME: &str = "HOME";
+#[cfg(target_family = "windows")]
+const HOME: &str = "USERPROFILE";
+
+/// Checks that `target` isn't a system directory, the home directory itself or matched by one of `forbidden`.
+pub fn check_target_allowed(target: &Path, forbidden: &[String]) -> Result<(), String> {
+    let canonical =
+        fs::canonicalize(target).map_err(|err| format!("Failed to resolve `{}`: {err}", target.display()))?;
+    let home = env::var_os(HOME).and_then(|home| fs::canonicalize(home).ok());
+    if home.as_deref() == Some(canonical.as_path()) {
+        return Err(format!(
+            "Refusing to link into your home directory `{}`. Pass --allow-dangerous-target if this is intended",
+            target.display()
+        ));
+    }
+
+    let path = canonical.to_string_lossy();
+    // Windows canonicalizes to verbatim paths, the patterns are written without the prefix.
+    let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+    let forbidden_by = DANGEROUS_TARGETS
+        .iter()
+        .copied()
+        .chain(forbidden.iter().map(String::as_str))
+        .find(|pattern| glob::matches(pattern, path));
+
+    match forbidden_by {
+        Some(pattern) => Err(format!(
+            "Refusing to link into `{}`, it matches the forbidden target `{pattern}`. Pass --allow-dangerous-target \
+             if this is intended",
+            target.display()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Bytes available to the current user on the filesystem of `dir`.
+#[cfg(target_family = "unix")]
+pub fn free_space(dir: &Path) -> Result<u64, String> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|_| format!("`{}` contains a NUL byte", dir.display()))?;
+    let mut stats = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL terminated and `stats` is large enough for a `statvfs`.
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(format!(
+            "Failed to get the free space of `{}`: {}",
+            dir.display(),
+            io::Error::last_os_error()
+        ));
+    }
+    // SAFETY: `statvfs` succeeded, so it filled `stats` in.
+    let stats = unsafe { stats.assume_init() };
+
+    #[allow(clippy::useless_conversion)] // The field types differ between platforms.
+    Ok(u64::from(stats.f_bavail).saturating_mul(u64::from(stats.f_frsize)))
+}
+
+/// Bytes available to the current user on the filesystem of `dir`.
+#[cfg(not(target_family = "unix"))]
+pub fn free_space(_dir: &Path) -> Result<u64, String> {
+    Err("Free space can only be checked on Unix".to_string())
+}
+
+/// Whether names in `dir` are limited to what Windows allows, as on Windows itself and on FAT and SMB filesystems.
+pub fn restricts_names(dir: &Path) -> bool {
+    // The filesystems known not to support some kinds of links are exactly those.
+    cfg!(target_family = "windows") || unsupported_links(dir).is_some()
+}
+
+/// Kinds of links a filesystem is known not to support.
+#[derive(Debug, Clone, Copy)]
+struct Unsupported {
+    filesystem: &'static str,
+    symlinks: bool,
+    hard_links: bool,
+}
+
+/// Checks that the filesystem of `dir` supports the kinds of links the run will create. Filesystems whose type
+/// can't be determined pass.
+pub fn check_link_support(dir: &Path, symlinks: bool, hard_links: bool) -> Result<(), String> {
+    let Some(unsupported) = unsupported_links(dir) else {
+        return Ok(());
+    };
+
+    let kind = match (symlinks && unsupported.symlinks, hard_links && unsupported.hard_links) {
+        (true, true) => "symbolic or hard links",
+        (true, false) => "symbolic links",
+        (false, true) => "hard links",
+        (false, false) => return Ok(()),
+    };
+    Err(format!(
+        "`{}` is on a {} filesystem, which doesn't support {kind}. Use --link-strategy copy instead",
+        dir.display(),
+        unsupported.filesystem
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn unsupported_links(dir: &Path) -> Option<Unsupported> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    const fn unsupported(filesystem: &'static str, symlinks: bool, hard_links: bool) -> Unsupported {
+        Unsupported {
+            filesystem,
+            symlinks,
+            hard_links,
+        }
+    }
+    // Magic numbers from `statfs(2)`. SMB shares only get symbolic links with extensions servers rarely enable.
+    const FILESYSTEMS: [(u32, Unsupported); 5] = [
+        (0x4d44, unsupported("FAT", true, true)),
+        (0x2011_bab0, unsupported("exFAT", true, true)),
+        (0x517b, unsupported("SMB", true, false)),
+        (0xff53_4d42, unsupported("CIFS", true, false)),
+        (0xfe53_4d42, unsupported("SMB2", true, false)),
+    ];
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stats = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `path` is NUL terminated and `stats` is large enough for a `statfs`.
+    if unsafe { libc::statfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: `statfs` succeeded, so it filled `stats` in.
+    let filesystem = u32::try_from(unsafe { stats.assume_init() }.f_type).ok()?;
+
+    FILESYSTEMS
+        .iter()
+        .find(|(magic, _)| *magic == filesystem)
+        .map(|(_, unsupported)| *unsupported)
+}
+
+/// Filesystem types are only detected on Linux.
+#[cfg(not(target_os = "linux"))]
+fn unsupported_links(_dir: &Path) -> Option<Unsupported> {
+    None
+}
@@ -0,0 +1,46 @@
+//! `--write-provenance` sidecars recording where a link came from.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{cli::ProvenanceFormat, summary};
+
+/// Extension appended to the name of a link to get the name of its sidecar.
+pub const EXTENSION: &str = "meta";
+
+/// Path of the sidecar for `link`: next to it, or at the same path relative to `target` under `dir`.
+pub fn sidecar_path(link: &Path, target: &Path, dir: Option<&Path>) -> PathBuf {
+    let mut sidecar = match dir {
+        Some(dir) => dir.join(
+            link.strip_prefix(target)
+                .unwrap_or_else(|_| Path::new(link.file_name().unwrap_or_default())),
+        ),
+        None => link.to_path_buf(),
+    };
+
+    let mut name = sidecar.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(EXTENSION);
+    sidecar.set_file_name(name);
+    sidecar
+}
+
+/// Writes the sidecar `sidecar`, recording that its link points at `source` and was created by a run started at
+/// `started`. Replaces any earlier sidecar.
+pub fn write(sidecar: &Path, source: &Path, started: &str, format: ProvenanceFormat) -> io::Result<()> {
+    if let Some(parent) = sidecar.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let contents = match format {
+        ProvenanceFormat::Text => format!("source={}\nlinked_at={started}\n", source.display()),
+        ProvenanceFormat::Json => format!(
+            "{{\"source\":\"{}\",\"linked_at\":\"{started}\"}}\n",
+            summary::json_escape(&source.to_string_lossy())
+        ),
+    };
+
+    fs::write(sidecar, contents)
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crate::{
+    audit::{AuditLog, Level},
+    cli::{Cli, OnOrphan},
+    device::Device,
+    index::{Index, Journal},
+    link::{Filesystem, LinkStrategy, Linker},
+    summary::Summary,
+};
+
+/// Confirmation prompts whose default adapts with `--adaptive-defaults`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Prompt {
+    CreateLink,
+    CreateDir,
+    Recurse,
+}
+
+/// How many identical answers in a row make an answer the new default.
+const ADAPT_AFTER: usize = 3;
+
+/// A destination that was moved out of the way of a new link.
+#[derive(Debug)]
+pub struct Displaced {
+    /// Where it is now.
+    pub moved_to: PathBuf,
+    /// Where it was.
+    pub dest: PathBuf,
+    /// Whether it was only kept aside to be deleted once the run is kept, not backed up.
+    pub discard: bool,
+}
+
+/// State shared by everything happening during a single invocation.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Default)]
+pub struct Run {
+    /// Base currently being linked.
+    pub base: PathBuf,
+    /// Device of the base currently being walked if `--one-file-system` is enabled.
+    pub base_device: Option<Device>,
+    /// Device of <TARGET> if `--auto-copy-cross-device` is enabled.
+    pub target_device: Option<Device>,
+    /// Whether `--sanitize-names` also replaces the characters Windows doesn't allow in names.
+    pub restricted_names: bool,
+    /// Directories this run wrote links into that `--touch-target-dirs` hasn't touched yet.
+    pub written_dirs: HashSet<PathBuf>,
+    /// Recreated directories `--lazy-dirs` hasn't created yet, outermost first.
+    pub pending_dirs: Vec<PathBuf>,
+    /// Answers to link creation prompts by source, for `--remember-answers`.
+    pub remembered: HashMap<PathBuf, bool>,
+    /// How many symbolic links the walk followed to get to the directory it's in, for `--symlink-follow-depth`.
+    pub symlinks_followed: usize,
+    pub summary: Summary,
+    /// Loaded `--index` file.
+    pub index: Option<Index>,
+    /// Links recorded by the run being continued with `--resume`.
+    pub resumed: Option<Index>,
+    /// The `--resume` file new links are written to.
+    pub journal: Option<Journal>,
+    /// The `--log-file` every operation is written to.
+    pub audit_log: Option<AuditLog>,
+    /// Loaded `--name-map`, keyed by source path relative to the base.
+    pub name_map: HashMap<PathBuf, String>,
+    /// When `--timeout-total` runs out.
+    pub deadline: Option<Instant>,
+    /// Whether the run was stopped because of `--timeout-total`.
+    pub timed_out: bool,
+    /// When the run started, as recorded in `--write-provenance` sidecars.
+    pub started: String,
+    /// Whether the user chose to skip the rest of the directory being walked, with `--interactive-skip-key`.
+    pub skip_rest_of_dir: bool,
+    /// Files picked, or not, in the `--multiselect-per-dir` list of the directories being walked.
+    pub selection: HashMap<PathBuf, bool>,
+    /// How many entries the walk has looked at so far, for `--batch-size`.
+    pub processed: u64,
+    /// How many directory handles the walk currently keeps open, shared with the `--parallel-dirs` workers.
+    open_dirs: Arc<AtomicUsize>,
+    /// `--layout` folders asked about so far and where they ended up, `None` if declined.
+    pub groups: HashMap<PathBuf, Option<PathBuf>>,
+    /// Links placed in `--layout` folders so far, for `--flatten-naming`.
+    pub flattened: HashSet<PathBuf>,
+    /// Whether the user was already told how to get permission to create symbolic links on Windows.
+    pub privilege_explained: bool,
+    /// Sources that failed to link, for `--failures-file`.
+    pub failures: Vec<PathBuf>,
+    /// Sources `--require-stable` skipped because they were still changing, to be retried later.
+    pub unstable: Vec<PathBuf>,
+    /// The `--atomic-run` staging directory and the <TARGET> it replaces.
+    pub staging: Option<(PathBuf, PathBuf)>,
+    /// Destinations moved out of the way of new links, for `--confirm-at-end` to put back.
+    pub displaced: Vec<Displaced>,
+    /// Links, directories and sidecars created so far, in order, for `--confirm-at-end` and orphan handling.
+    pub created: Vec<PathBuf>,
+    /// Whether an error made the run stop early.
+    pub failed_fatally: bool,
+    /// The last few answers given to each prompt, newest last.
+    answers: HashMap<Prompt, VecDeque<bool>>,
+    /// Creates the links, the filesystem unless a test swapped in a fake.
+    pub linker: Option<Arc<dyn Linker>>,
+    /// How many more `--parallel-dirs` workers may start, shared by the run and all its workers.
+    pub workers: Option<Arc<AtomicUsize>>,
+    /// Metadata of sources read so far, for `--inode-cache`.
+    metadata: HashMap<PathBuf, Metadata>,
+}
+
+impl Run {
+    /// Counts a failure to link `source` and remembers it for `--failures-file`.
+    pub fn fail(&mut self, source: &Path) {
+        self.summary.failed += 1;
+        self.failures.push(source.to_path_buf());
+    }
+
+    /// Writes an operation to the `--log-file`. Stops logging if writing fails, the run itself goes on.
+    pub fn log(&mut self, level: Level, action: &str, source: Option<&Path>, dest: Option<&Path>, result: &str) {
+        let Some(audit_log) = &mut self.audit_log else {
+            return;
+        };
+
+        let dest = dest.map(|dest| final_path(self.staging.as_ref(), dest));
+        if let Err(err) = audit_log.record(level, action, source, dest.as_deref(), result) {
+            eprintln!("Failed to write to --log-file, no longer logging: {err}");
+            self.audit_log = None;
+        }
+    }
+
+    /// Links `original` to `link` with `strategy`.
+    pub fn link(&self, strategy: LinkStrategy, original: &Path, link: &Path) -> io::Result<()> {
+        match &self.linker {
+            Some(linker) => linker.link(strategy, original, link),
+            None => Filesystem.link(strategy, original, link),
+        }
+    }
+
+    /// A run for a `--parallel-dirs` worker walking a subtree, with the settings of this one and nothing done yet.
+    #[allow(clippy::clone_on_copy)] // `Device` is an `OsString` on Windows.
+    pub fn fork(&self) -> Self {
+        Self {
+            base: self.base.clone(),
+            base_device: self.base_device.clone(),
+            target_device: self.target_device.clone(),
+            restricted_names: self.restricted_names,
+            remembered: self.remembered.clone(),
+            symlinks_followed: self.symlinks_followed,
+            summary: Summary {
+                bytes_linked: self.summary.bytes_linked.map(|_| 0),
+                ..Summary::default()
+            },
+            name_map: self.name_map.clone(),
+            deadline: self.deadline,
+            started: self.started.clone(),
+            open_dirs: Arc::clone(&self.open_dirs),
+            privilege_explained: self.privilege_explained,
+            staging: self.staging.clone(),
+            linker: self.linker.clone(),
+            workers: self.workers.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds what a worker `fork`ed from this run did.
+    pub fn absorb(&mut self, worker: Self) {
+        self.summary.add(worker.summary);
+        self.written_dirs.extend(worker.written_dirs);
+        self.timed_out |= worker.timed_out;
+        self.processed += worker.processed;
+        self.privilege_explained |= worker.privilege_explained;
+        self.failures.extend(worker.failures);
+        self.unstable.extend(worker.unstable);
+        self.displaced.extend(worker.displaced);
+        self.created.extend(worker.created);
+        self.failed_fatally |= worker.failed_fatally;
+    }
+
+    /// Counts one more open directory handle, unless that would reach `max` from `--max-open-dirs`.
+    pub fn claim_open_dir(&self, max: Option<usize>) -> bool {
+        self.open_dirs
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                max.is_none_or(|max| open + 1 < max).then_some(open + 1)
+            })
+            .is_ok()
+    }
+
+    /// Hands the directory handle counted with `claim_open_dir` back once it's closed.
+    pub fn release_open_dir(&self) {
+        self.open_dirs.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Takes one of the spare `--parallel-dirs` workers, if there is one.
+    pub fn claim_worker(&self) -> bool {
+        self.workers.as_ref().is_some_and(|spare| {
+            spare
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |spare| spare.checked_sub(1))
+                .is_ok()
+        })
+    }
+
+    /// Hands the worker taken with `claim_worker` back once it's done.
+    pub fn release_worker(&self) {
+        if let Some(spare) = &self.workers {
+            spare.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Remembers that the run created `path`, if `--confirm-at-end` may have to remove it again or orphan handling
+    /// has to leave it alone.
+    pub fn created(&mut self, cli: &Cli, path: &Path) {
+        if cli.confirm_at_end() || cli.on_orphan() != OnOrphan::Ignore {
+            self.created.push(self.final_path(path));
+        }
+    }
+
+    /// Where `path` ends up once the run is done, in <TARGET> rather than the `--atomic-run` staging directory.
+    pub fn final_path(&self, path: &Path) -> PathBuf {
+        final_path(self.staging.as_ref(), path)
+    }
+
+    /// Metadata of `source`, following symbolic links. With `--inode-cache` it's only read once per source.
+    pub fn source_metadata(&mut self, cli: &Cli, source: &Path) -> io::Result<Metadata> {
+        if !cli.inode_cache() {
+            return fs::metadata(source);
+        }
+
+        if let Some(metadata) = self.metadata.get(source) {
+            return Ok(metadata.clone());
+        }
+        let metadata = fs::metadata(source)?;
+        self.metadata.insert(source.to_path_buf(), metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Drops the cached metadata of `source` after the run changed it, e.g. by hard linking it.
+    pub fn forget_metadata(&mut self, source: &Path) {
+        self.metadata.remove(source);
+    }
+
+    /// Path of `source` relative to the current base. A base that is a single file is relative to its parent.
+    pub fn relative_source<'a>(&self, source: &'a Path) -> &'a Path {
+        if self.base.is_file() {
+            return source.file_name().map_or(source, Path::new);
+        }
+
+        source.strip_prefix(&self.base).unwrap_or(source)
+    }
+
+    /// Name `--name-map` gives to links pointing at `source`.
+    pub fn mapped_name(&self, source: &Path) -> Option<&str> {
+        self.name_map.get(self.relative_source(source)).map(String::as_str)
+    }
+
+    /// Checks whether `--timeout-total` has run out, reporting it the first time it does.
+    pub fn out_of_time(&mut self) -> bool {
+        if !self.timed_out && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            eprintln!("Timed out, stopping");
+            self.timed_out = true;
+        }
+
+        self.timed_out
+    }
+
+    /// Default answer of `prompt`. Yes, unless `--adaptive-defaults` picked up on the user consistently answering no.
+    pub fn confirm_default(&self, cli: &Cli, prompt: Prompt) -> bool {
+        if !cli.adaptive_defaults() {
+            return true;
+        }
+
+        match self.answers.get(&prompt) {
+            Some(answers) if answers.len() == ADAPT_AFTER && answers.iter().all(|&answer| answer == answers[0]) => {
+                answers[0]
+            }
+            _ => true,
+        }
+    }
+
+    /// Remembers the answer the user gave to `prompt`. Aborted prompts are ignored.
+    pub fn remember_answer(&mut self, prompt: Prompt, answer: Option<bool>) {
+        let Some(answer) = answer else {
+            return;
+        };
+
+        let answers = self.answers.entry(prompt).or_default();
+        if answers.len() == ADAPT_AFTER {
+            answers.pop_front();
+        }
+        answers.push_back(answer);
+    }
+}
+
+fn final_path(staging: Option<&(PathBuf, PathBuf)>, path: &Path) -> PathBuf {
+    match staging.and_then(|(staging, target)| Some(target.join(path.strip_prefix(staging).ok()?))) {
+        Some(path) => path,
+        None => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use clap::ValueEnum;
+
+use crate::size;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+/// Counts of what happened during a run.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub links_created: u64,
+    pub dirs_created: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    /// Total size of the files links were created to for `--report-bytes`, `None` without it.
+    pub bytes_linked: Option<u64>,
+    /// Links created per source extension for `--summary-by-ext`, empty without it. Files without an extension are
+    /// counted under `""`.
+    pub by_extension: BTreeMap<String, ExtensionTotals>,
+}
+
+/// How many links were created to files with one extension and how large those files are in total.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExtensionTotals {
+    pub links: u64,
+    pub bytes: u64,
+}
+
+impl Summary {
+    /// `(key, human readable label, value)` of every counter.
+    fn fields(&self) -> [(&'static str, &'static str, u64); 4] {
+        [
+            ("links_created", "Links created", self.links_created),
+            ("dirs_created", "Directories created", self.dirs_created),
+            ("skipped", "Skipped", self.skipped),
+            ("failed", "Failed", self.failed),
+        ]
+    }
+
+    /// Counters as `TLU_<KEY>` environment variables.
+    pub fn env_vars(&self) -> impl Iterator<Item = (String, String)> {
+        self.fields()
+            .into_iter()
+            .map(|(key, _, value)| (format!("TLU_{}", key.to_uppercase()), value.to_string()))
+            .chain(self.bytes_linked.map(|bytes| ("TLU_BYTES_LINKED".to_string(), bytes.to_string())))
+    }
+
+    /// Adds the counts of `other`, e.g. of a `--parallel-dirs` worker.
+    pub fn add(&mut self, other: Self) {
+        self.links_created += other.links_created;
+        self.dirs_created += other.dirs_created;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        if let (Some(bytes), Some(other)) = (&mut self.bytes_linked, other.bytes_linked) {
+            *bytes += other;
+        }
+        for (extension, totals) in other.by_extension {
+            let sum = self.by_extension.entry(extension).or_default();
+            sum.links += totals.links;
+            sum.bytes += totals.bytes;
+        }
+    }
+
+    /// Counts a link to a file with `extension` that is `bytes` large for `--summary-by-ext`.
+    pub fn count_extension(&mut self, extension: &str, bytes: u64) {
+        let totals = self.by_extension.entry(extension.to_string()).or_default();
+        totals.links += 1;
+        totals.bytes += bytes;
+    }
+
+    /// Counters on a single line, for `--batch-size` progress reports.
+    pub fn line(&self) -> String {
+        let fields: Vec<String> = self
+            .fields()
+            .iter()
+            .map(|(_, label, value)| format!("{label}: {value}"))
+            .collect();
+        fields.join(", ")
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        let fields = self.fields();
+        let mut out = String::new();
+
+        match format {
+            ReportFormat::Text => {
+                for (_, label, value) in fields {
+                    writeln!(out, "{label}: {value}").unwrap();
+                }
+                if let Some(bytes) = self.bytes_linked {
+                    writeln!(out, "Bytes linked: {}", size::format(bytes)).unwrap();
+                }
+                if !self.by_extension.is_empty() {
+                    writeln!(out, "By extension:").unwrap();
+                }
+                for (extension, totals) in &self.by_extension {
+                    let extension = if extension.is_empty() { "(none)".to_string() } else { format!(".{extension}") };
+                    writeln!(out, "  {extension}: {} ({})", totals.links, size::format(totals.bytes)).unwrap();
+                }
+            }
+            ReportFormat::Json => {
+                let mut body: Vec<String> = fields
+                    .iter()
+                    .map(|(key, _, value)| format!("\"{key}\":{value}"))
+                    .collect();
+                if let Some(bytes) = self.bytes_linked {
+                    body.push(format!("\"bytes_linked\":{bytes}"));
+                }
+                if !self.by_extension.is_empty() {
+                    let extensions: Vec<String> = self
+                        .by_extension
+                        .iter()
+                        .map(|(extension, totals)| {
+                            format!(
+                                "\"{}\":{{\"links\":{},\"bytes\":{}}}",
+                                json_escape(extension),
+                                totals.links,
+                                totals.bytes
+                            )
+                        })
+                        .collect();
+                    body.push(format!("\"by_extension\":{{{}}}", extensions.join(",")));
+                }
+                writeln!(out, "{{{}}}", body.join(",")).unwrap();
+            }
+            ReportFormat::Csv => {
+                let mut keys: Vec<&str> = fields.iter().map(|(key, _, _)| *key).collect();
+                let mut values: Vec<String> = fields.iter().map(|(_, _, value)| value.to_string()).collect();
+                if let Some(bytes) = self.bytes_linked {
+                    keys.push("bytes_linked");
+                    values.push(bytes.to_string());
+                }
+                writeln!(out, "{}", keys.join(",")).unwrap();
+                writeln!(out, "{}", values.join(",")).unwrap();
+                // A second table after a blank line, there's no way to nest it into the first.
+                if !self.by_extension.is_empty() {
+                    writeln!(out).unwrap();
+                    writeln!(out, "extension,links,bytes").unwrap();
+                }
+                for (extension, totals) in &self.by_extension {
+                    writeln!(out, "{},{},{}", csv_escape(extension), totals.links, totals.bytes).unwrap();
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Escapes `text` for use inside a JSON string.
+pub fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", u32::from(c)).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn csv_escape(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,54 @@
+//! Copying extended attributes for `--preserve-xattr`.
+
+use std::{io, path::Path};
+
+/// Copies every extended attribute of `from` to `to`. ACLs are stored as extended attributes, so they come along.
+#[cfg(target_os = "linux")]
+pub fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+
+    // SAFETY: `from` is NUL terminated and `read` passes a buffer at least `size` bytes long.
+    let names = read(|buffer, size| unsafe { libc::listxattr(from.as_ptr(), buffer.cast(), size) })?;
+    for name in names.split(|&byte| byte == 0).filter(|name| !name.is_empty()) {
+        let name = CString::new(name)?;
+        // SAFETY: As above, `name` is NUL terminated too.
+        let value =
+            read(|buffer, size| unsafe { libc::getxattr(from.as_ptr(), name.as_ptr(), buffer.cast(), size) })?;
+        // SAFETY: `to` and `name` are NUL terminated and `value` is `value.len()` bytes long.
+        if unsafe { libc::setxattr(to.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a value of unknown size with one of the `*xattr` calls, which report the size they need when given no
+/// buffer. Retries if the value grew in between.
+#[cfg(target_os = "linux")]
+fn read(mut call: impl FnMut(*mut u8, usize) -> isize) -> io::Result<Vec<u8>> {
+    loop {
+        let Ok(size) = usize::try_from(call(std::ptr::null_mut(), 0)) else {
+            return Err(io::Error::last_os_error());
+        };
+        let mut buffer = vec![0; size];
+        if let Ok(read) = usize::try_from(call(buffer.as_mut_ptr(), buffer.len())) {
+            buffer.truncate(read);
+            return Ok(buffer);
+        }
+
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+/// Copies every extended attribute of `from` to `to`.
+#[cfg(not(target_os = "linux"))]
+pub fn copy(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "extended attributes are only copied on Linux"))
+}